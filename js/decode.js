@@ -0,0 +1,123 @@
+// Decodes the buffer from `Compiler.compile_binary` into the same tree
+// `Compiler.compile_with(source, "json")` returns. The format is described
+// in src/markdown/render/binary.rs.
+
+const VERSION = 4;
+
+const KINDS = [
+  "Document", "BlockQuote", "Empty", "UnorderedList", "OrderedList",
+  "ListItem", "Heading1", "Heading2", "Heading3", "Heading4", "Heading5",
+  "Heading6", "Paragraph", "EmptyLine", "Plaintext", "Whitespace",
+  "Strikethrough", "MathInline", "WikiLink", "Mention", "Hashtag", "Emoji",
+  "Highlight", "Superscript", "Subscript", "InlineHtml", "CriticAddition",
+  "CriticDeletion", "CriticSubstitution", "CriticHighlight", "CriticComment",
+  "SmartPunctuation",
+];
+
+const HTML_KINDS = [
+  "Open", "Close", "Comment", "ProcessingInstruction", "Declaration", "Cdata",
+];
+
+export function decode(bytes) {
+  const view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+  const utf8 = new TextDecoder("utf-8", { fatal: true });
+  let p = 0;
+
+  const byte = () => {
+    if (p >= bytes.length) throw new Error("truncated tree");
+    return bytes[p++];
+  };
+  // Varints stay below 2^53, hashes are stored whole and read as hex
+  const varint = () => {
+    let n = 0;
+    let scale = 1;
+    for (;;) {
+      const b = byte();
+      n += (b & 0x7f) * scale;
+      if (b < 0x80) return n;
+      scale *= 128;
+    }
+  };
+  const signed = () => {
+    const n = varint();
+    return n % 2 ? -(n + 1) / 2 : n / 2;
+  };
+  const entry = (table, i, what) => {
+    if (!(i >= 0 && i < table.length)) throw new Error(`unknown ${what} ${i}`);
+    return table[i];
+  };
+  const hash = (at) => view.getBigUint64(at, true).toString(16).padStart(16, "0");
+  const string = () => entry(strings, varint(), "string");
+  const optional = () => {
+    const i = varint();
+    return i ? entry(strings, i - 1, "string") : null;
+  };
+  const span = (start) => {
+    const s = start + signed();
+    return [s, s + varint()];
+  };
+
+  const link = (start) => {
+    const flags = byte();
+    const target = span(start);
+    const anchor = flags & 2 ? span(start) : null;
+    const label = flags & 4 ? span(start) : null;
+    return {
+      embed: !!(flags & 1),
+      target,
+      anchor,
+      label,
+      url: flags & 8 ? string() : null,
+      exists: flags & 32 ? !!(flags & 16) : null,
+    };
+  };
+
+  const node = (parent) => {
+    const kind = entry(KINDS, varint() - 1, "kind");
+    const flags = byte();
+    const start = parent + signed();
+    const end = start + varint();
+    if (p + 16 > bytes.length) throw new Error("truncated tree");
+    const merkle = hash(p);
+    const position = hash(p + 8);
+    p += 16;
+    const n = {
+      kind, span: [start, end], merkle, position, children: null, text: null,
+    };
+    if (flags & 1) n.text = string();
+    if (flags & 2) {
+      const count = varint();
+      n.children = [];
+      for (let i = 0; i < count; i++) n.children.push(node(start));
+    }
+    if (flags & 4) n.link = link(start);
+    if (flags & 8) {
+      const kind = entry(HTML_KINDS, byte(), "HTML kind");
+      n.html = { kind, name: optional(), self_closing: !!byte() };
+    }
+    if (flags & 16) {
+      const attributes = span(start);
+      const id = optional();
+      const classes = [];
+      for (let i = varint(); i > 0; i--) classes.push(string());
+      const pairs = [];
+      for (let i = varint(); i > 0; i--) pairs.push([string(), string()]);
+      n.attributes = { span: attributes, id, classes, pairs };
+    }
+    if (flags & 32) n.version = varint();
+    if (flags & 64) n.loose = !!(flags & 128);
+    return n;
+  };
+
+  if (byte() !== VERSION) throw new Error("unsupported tree version");
+  const strings = [];
+  for (let i = varint(); i > 0; i--) {
+    const length = varint();
+    if (p + length > bytes.length) throw new Error("truncated tree");
+    strings.push(utf8.decode(bytes.subarray(p, p + length)));
+    p += length;
+  }
+  const root = node(0);
+  if (p !== bytes.length) throw new Error("trailing bytes after tree");
+  return root;
+}
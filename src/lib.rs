@@ -1,13 +1,34 @@
-#![feature(test)]
-#![feature(or_patterns)]
+#![cfg_attr(test, feature(test))]
 
-mod markdown;
+pub mod markdown;
 mod utils;
 
+use std::cell::RefCell;
+
+use js_sys::{Function, Reflect};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-pub struct Compiler {}
+pub struct Compiler {
+    options: markdown::Options,
+    html: markdown::html::Options,
+    format: markdown::markdown::Options,
+    text: markdown::text::Options,
+    ansi: markdown::ansi::Options,
+    latex: markdown::latex::Options,
+    resolver: Option<Function>,
+    // Kept in a cell so that compiling takes `&self` like rendering does
+    last: RefCell<Last>,
+}
+
+/// Source, parse and tree of the last `compile` or `edit`.
+#[derive(Default)]
+struct Last {
+    source: String,
+    // The parse along with the options it used
+    doc: Option<(markdown::Options, markdown::Node)>,
+    tree: Option<markdown::json::N>,
+}
 
 impl Default for Compiler {
     fn default() -> Self {
@@ -19,11 +40,448 @@ impl Default for Compiler {
 impl Compiler {
     pub fn new() -> Self {
         utils::set_panic_hook();
-        Compiler {}
+        Compiler {
+            options: markdown::Options::default(),
+            html: markdown::html::Options::default(),
+            format: markdown::markdown::Options::default(),
+            text: markdown::text::Options::default(),
+            ansi: markdown::ansi::Options::default(),
+            latex: markdown::latex::Options::default(),
+            resolver: None,
+            last: RefCell::default(),
+        }
+    }
+
+    pub fn set_math(&mut self, enabled: bool) {
+        self.options.math = enabled;
+    }
+
+    pub fn set_mentions(&mut self, enabled: bool) {
+        self.options.mentions = enabled;
+    }
+
+    pub fn set_hashtags(&mut self, enabled: bool) {
+        self.options.hashtags = enabled;
+    }
+
+    pub fn set_highlight(&mut self, enabled: bool) {
+        self.options.highlight = enabled;
+    }
+
+    pub fn set_superscript(&mut self, enabled: bool) {
+        self.options.superscript = enabled;
+    }
+
+    pub fn set_subscript(&mut self, enabled: bool) {
+        self.options.subscript = enabled;
+    }
+
+    pub fn set_critic_markup(&mut self, enabled: bool) {
+        self.options.critic_markup = enabled;
+    }
+
+    pub fn set_smart_punctuation(&mut self, enabled: bool) {
+        self.options.smart_punctuation = enabled;
+    }
+
+    /// Escapes raw HTML and drops unsafe URLs and attributes in
+    /// `compile_html`.
+    pub fn set_html_safe(&mut self, enabled: bool) {
+        self.html.safe = enabled;
+    }
+
+    /// Writes soft line breaks as `"newline"`, `"space"` or `"break"` (`<br />`).
+    pub fn set_html_soft_break(&mut self, soft_break: &str) {
+        self.html.soft_break = match soft_break {
+            "space" => markdown::html::SoftBreak::Space,
+            "break" => markdown::html::SoftBreak::Break,
+            _ => markdown::html::SoftBreak::Newline,
+        };
+    }
+
+    /// Adds `data-sourcepos` attributes to blocks in `compile_html`.
+    pub fn set_html_source_pos(&mut self, enabled: bool) {
+        self.html.source_pos = enabled;
+    }
+
+    /// Uses `bullet` (`*`, `-` or `+`) for unordered lists in `format`.
+    /// Any other character would not read back as a list and is ignored.
+    pub fn set_format_bullet(&mut self, bullet: char) {
+        if matches!(bullet, '*' | '-' | '+') {
+            self.format.bullet = bullet;
+        }
+    }
+
+    /// Numbers ordered list items the same instead of counting up in `format`.
+    pub fn set_format_same_numbers(&mut self, enabled: bool) {
+        self.format.numbering = match enabled {
+            true => markdown::markdown::Numbering::Same,
+            false => markdown::markdown::Numbering::Increment,
+        };
+    }
+
+    /// Uses `delimiter` (`.` or `)`) after ordered list numbers in `format`.
+    /// Any other character is ignored.
+    pub fn set_format_delimiter(&mut self, delimiter: char) {
+        if matches!(delimiter, '.' | ')') {
+            self.format.delimiter = delimiter;
+        }
+    }
+
+    /// Underlines level 1 and 2 headings in `format` instead of starting
+    /// them with `#`.
+    pub fn set_format_setext(&mut self, enabled: bool) {
+        self.format.headings = match enabled {
+            true => markdown::markdown::Headings::Setext,
+            false => markdown::markdown::Headings::Atx,
+        };
     }
 
+    /// Wraps paragraphs at `width` columns in `format`, 0 keeps line breaks.
+    pub fn set_format_width(&mut self, width: usize) {
+        self.format.width = width;
+    }
+
+    /// Writes the alt text of embedded images in `compile_text`.
+    pub fn set_text_alt_text(&mut self, enabled: bool) {
+        self.text.alt_text = enabled;
+    }
+
+    /// Wraps `compile_ansi` output at `width` columns, 0 disables wrapping.
+    pub fn set_ansi_width(&mut self, width: usize) {
+        self.ansi.width = width;
+    }
+
+    /// Styles `compile_ansi` output with colors and text attributes.
+    pub fn set_ansi_color(&mut self, enabled: bool) {
+        self.ansi.color = enabled;
+    }
+
+    /// Makes wiki links in `compile_ansi` output clickable in terminals
+    /// supporting OSC 8.
+    pub fn set_ansi_hyperlinks(&mut self, enabled: bool) {
+        self.ansi.hyperlinks = enabled;
+    }
+
+    /// Maps level one headings to `\chapter` in `compile_latex`.
+    pub fn set_latex_chapters(&mut self, enabled: bool) {
+        self.latex.chapters = enabled;
+    }
+
+    /// Makes `compile_latex` write a complete document with a preamble.
+    pub fn set_latex_standalone(&mut self, enabled: bool) {
+        self.latex.standalone = enabled;
+    }
+
+    /// Sets a callback mapping wiki link targets to `{ url, exists }`.
+    pub fn set_wiki_resolver(&mut self, resolver: Option<Function>) {
+        self.resolver = resolver;
+    }
+
+    /// Lists the changes from the tree of the last `compile` or `edit` to
+    /// this one as JSON `[{ op, path, node | text }]`. The first call
+    /// replaces the root, at path `[]`, with the whole tree.
     pub fn compile(&self, source: &str) -> String {
-        let doc = markdown::parse(source);
-        markdown::json::render(source, doc)
+        let doc = markdown::parse(source, &self.options);
+        self.patch(source.to_string(), doc)
+    }
+
+    /// Replaces bytes `start..end` of the source last compiled or edited
+    /// with `replacement`, parsing only the blocks around them again, and
+    /// returns the changes like `compile`.
+    pub fn edit(&self, start: usize, end: usize, replacement: &str) -> Result<String, JsError> {
+        let mut last = self.last.borrow_mut();
+        let valid = start <= end
+            && last.source.is_char_boundary(start)
+            && last.source.is_char_boundary(end);
+        if !valid {
+            return Err(JsError::new(&format!("invalid edit {}..{}", start, end)));
+        }
+        let source = [&last.source[..start], replacement, &last.source[end..]].concat();
+        let doc = match last.doc.take() {
+            Some((options, doc)) if options == self.options => {
+                let edit = (start, end);
+                markdown::reparse(&source, doc, edit, replacement.len(), &options)
+            }
+            _ => markdown::parse(&source, &self.options),
+        };
+        // The resolver may compile again, so the cell is not borrowed while
+        // the tree is built
+        drop(last);
+        Ok(self.patch(source, doc))
+    }
+
+    /// Renders with the `markdown::Renderer` registered under `format`, one
+    /// of `json`, `html`, `latex`, `ansi` and `markdown`, with the options
+    /// set for it.
+    pub fn compile_with(&self, source: &str, format: &str) -> Result<String, JsError> {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        let resolver = resolver.as_ref().map(|r| r as &dyn markdown::Resolver);
+        let mut renderer: Box<dyn markdown::Renderer<Output = String>> = match format {
+            "json" => Box::new(markdown::json::Json::new(resolver)),
+            "html" => Box::new(markdown::html::Writer::new(resolver, &self.html)),
+            "latex" => Box::new(markdown::latex::Writer::new(resolver, &self.latex)),
+            "ansi" => Box::new(markdown::ansi::Writer::new(resolver, &self.ansi)),
+            "markdown" => Box::new(markdown::markdown::Formatter::new(&self.format)),
+            _ => return Err(JsError::new(&format!("unknown format {:?}", format))),
+        };
+        Ok(markdown::render_with(&mut *renderer, source, &doc))
+    }
+
+    /// Builds the tree `compile_with(source, "json")` returns as JS
+    /// objects, without going through a JSON string.
+    pub fn compile_value(&self, source: &str) -> JsValue {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::js::render(
+            source,
+            &doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+        )
+    }
+
+    /// Encodes the tree `compile_with(source, "json")` returns into a
+    /// compact buffer, which js/decode.js turns back into the same objects.
+    pub fn compile_binary(&self, source: &str) -> Vec<u8> {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::binary::render(
+            source,
+            &doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+        )
+    }
+
+    pub fn compile_html(&self, source: &str) -> String {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::html::render(
+            source,
+            &doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+            &self.html,
+        )
+    }
+
+    /// Renders styled text for a terminal.
+    pub fn compile_ansi(&self, source: &str) -> String {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::ansi::render(
+            source,
+            &doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+            &self.ansi,
+        )
+    }
+
+    pub fn compile_latex(&self, source: &str) -> String {
+        let doc = markdown::parse(source, &self.options);
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::latex::render(
+            source,
+            &doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+            &self.latex,
+        )
+    }
+
+    /// Strips the markup as JSON `{ text, map: [{ output, source, copied }] }`,
+    /// the map relates byte ranges of the text to the source they came from.
+    pub fn compile_text(&self, source: &str) -> String {
+        let doc = markdown::parse(source, &self.options);
+        let text = markdown::text::render(source, &doc, &self.text);
+        serde_json::to_string(&text).unwrap()
+    }
+
+    /// Maps `start..end` of the `compile_text` output back to the source as
+    /// JSON `[start, end]`, or `null` for text such as bullets.
+    pub fn text_source_span(&self, source: &str, start: usize, end: usize) -> String {
+        let doc = markdown::parse(source, &self.options);
+        let text = markdown::text::render(source, &doc, &self.text);
+        serde_json::to_string(&text.source_span(start, end)).unwrap()
+    }
+
+    /// Rewrites the document as canonical markdown.
+    pub fn format(&self, source: &str) -> String {
+        let doc = markdown::parse(source, &self.options);
+        markdown::markdown::render(source, &doc, &self.format)
+    }
+
+    /// Lists emoji shortcodes starting with `prefix` as JSON
+    /// `[{ shortcode, emoji }]`.
+    pub fn emoji_completions(&self, prefix: &str) -> String {
+        serde_json::to_string(&markdown::emoji::completions(prefix)).unwrap()
+    }
+
+    /// Lists every hashtag as JSON `[{ name, span }]`, even when hashtags
+    /// are not rendered.
+    pub fn collect_tags(&self, source: &str) -> String {
+        let options = markdown::Options {
+            hashtags: true,
+            ..self.options
+        };
+        let doc = markdown::parse(source, &options);
+        let tags = markdown::tags::collect_tags(source, &doc);
+        serde_json::to_string(&tags).unwrap()
+    }
+
+    /// Lists every mention as JSON `[{ name, span }]`, even when mentions
+    /// are not rendered.
+    pub fn collect_mentions(&self, source: &str) -> String {
+        let options = markdown::Options {
+            mentions: true,
+            ..self.options
+        };
+        let doc = markdown::parse(source, &options);
+        let mentions = markdown::tags::collect_mentions(source, &doc);
+        serde_json::to_string(&mentions).unwrap()
+    }
+
+    /// Applies every CriticMarkup change and returns the new source.
+    pub fn accept_all_changes(&self, source: &str) -> String {
+        markdown::review::accept_all(source, &self.parse_critic(source))
+    }
+
+    /// Drops every CriticMarkup change and returns the new source.
+    pub fn reject_all_changes(&self, source: &str) -> String {
+        markdown::review::reject_all(source, &self.parse_critic(source))
+    }
+
+    /// Applies the CriticMarkup change spanning `start..end`.
+    pub fn accept_change(&self, source: &str, start: usize, end: usize) -> String {
+        markdown::review::accept(source, &self.parse_critic(source), (start, end))
+    }
+
+    /// Drops the CriticMarkup change spanning `start..end`.
+    pub fn reject_change(&self, source: &str, start: usize, end: usize) -> String {
+        markdown::review::reject(source, &self.parse_critic(source), (start, end))
+    }
+}
+
+impl Compiler {
+    /// The tree `compile` diffs.
+    fn tree(&self, source: &str, doc: &markdown::Node) -> markdown::json::N {
+        let resolver = self.resolver.as_ref().map(JsResolver);
+        markdown::json::tree(
+            source,
+            doc,
+            resolver.as_ref().map(|r| r as &dyn markdown::Resolver),
+        )
+    }
+
+    fn patch(&self, source: String, doc: markdown::Node) -> String {
+        let tree = self.tree(&source, &doc);
+        let patch = match &self.last.borrow().tree {
+            Some(last) => markdown::diff::diff(last, &tree),
+            None => vec![markdown::diff::Op::Replace {
+                path: vec![],
+                node: &tree,
+            }],
+        };
+        let json = serde_json::to_string(&patch).unwrap();
+        self.retain(source, doc, tree);
+        json
+    }
+
+    fn retain(&self, source: String, doc: markdown::Node, tree: markdown::json::N) {
+        *self.last.borrow_mut() = Last {
+            source,
+            doc: Some((self.options, doc)),
+            tree: Some(tree),
+        };
+    }
+
+    /// Changes are found even when CriticMarkup is not rendered.
+    fn parse_critic(&self, source: &str) -> markdown::Node {
+        let options = markdown::Options {
+            critic_markup: true,
+            ..self.options
+        };
+        markdown::parse(source, &options)
+    }
+}
+
+struct JsResolver<'a>(&'a Function);
+
+impl<'a> markdown::Resolver for JsResolver<'a> {
+    fn resolve(&self, target: &str) -> Option<markdown::Resolution> {
+        let result = self.0.call1(&JsValue::NULL, &target.into()).ok()?;
+        let url = Reflect::get(&result, &"url".into()).ok()?.as_string()?;
+        let exists = Reflect::get(&result, &"exists".into())
+            .ok()
+            .and_then(|e| e.as_bool())
+            .unwrap_or(true);
+        Some(markdown::Resolution { url, exists })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect_default_options() {
+        let compiler = Compiler::new();
+        let source = "#a @b";
+        assert_eq!(
+            compiler.collect_tags(source),
+            r#"[{"name":"a","span":[0,2]}]"#
+        );
+        assert_eq!(
+            compiler.collect_mentions(source),
+            r#"[{"name":"b","span":[3,5]}]"#
+        );
+    }
+
+    #[test]
+    fn test_compile_with() {
+        let mut compiler = Compiler::new();
+        compiler.set_math(true);
+        let source = "# A {#a}\n\n* $x$ ~~b~~\n* c\n\n> d\n";
+        let doc = markdown::parse(source, &compiler.options);
+        let formats = [
+            ("json", markdown::json::render(source, &doc, None)),
+            ("html", compiler.compile_html(source)),
+            ("latex", compiler.compile_latex(source)),
+            ("ansi", compiler.compile_ansi(source)),
+            ("markdown", compiler.format(source)),
+        ];
+        for (format, expected) in formats {
+            assert_eq!(compiler.compile_with(source, format).ok(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_format_markers() {
+        let mut compiler = Compiler::new();
+        compiler.set_format_bullet('x');
+        compiler.set_format_delimiter('x');
+        assert_eq!(compiler.format("- a"), "* a\n");
+        assert_eq!(compiler.format("1) a"), "1. a\n");
+        // Invalid markers keep the ones set before
+        compiler.set_format_bullet('+');
+        compiler.set_format_delimiter(')');
+        compiler.set_format_bullet('1');
+        compiler.set_format_delimiter('*');
+        assert_eq!(compiler.format("- a"), "+ a\n");
+        assert_eq!(compiler.format("1. a"), "1) a\n");
+    }
+
+    #[test]
+    fn test_compile_patch() {
+        // Compiling through a shared reference keeps the last tree
+        let compiler = &Compiler::new();
+        let patch = compiler.compile("# a\n\nb");
+        assert!(
+            patch.starts_with(r#"[{"op":"replace","path":[],"#),
+            "{}",
+            patch
+        );
+        assert_eq!(compiler.compile("# a\n\nb"), "[]");
+        let patch = compiler.edit(5, 6, "c").ok().unwrap();
+        assert!(patch.contains(r#""op":"#), "{}", patch);
+        assert_eq!(compiler.compile("# a\n\nc"), "[]");
     }
 }
@@ -0,0 +1,236 @@
+use serde::Serialize;
+
+use crate::markdown::json::{A, N};
+
+/// A change turning one tree into the next. Paths are child indices from
+/// the root and refer to the tree as left by the operations before.
+#[derive(Serialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op<'a> {
+    /// Inserts a node so that it ends up at `path`
+    Insert {
+        path: Vec<usize>,
+        node: &'a N,
+    },
+    Remove {
+        path: Vec<usize>,
+    },
+    Replace {
+        path: Vec<usize>,
+        node: &'a N,
+    },
+    /// Changes the text of a leaf that is otherwise unchanged
+    UpdateText {
+        path: Vec<usize>,
+        text: &'a str,
+    },
+}
+
+/// Lists the operations turning `old` into `new`. Subtrees with the same
+/// merkle hash are skipped without looking inside them.
+pub fn diff<'a>(old: &N, new: &'a N) -> Vec<Op<'a>> {
+    let mut ops = vec![];
+    diff_node(old, new, &mut vec![], &mut ops);
+    ops
+}
+
+fn diff_node<'a>(old: &N, new: &'a N, path: &mut Vec<usize>, ops: &mut Vec<Op<'a>>) {
+    if old.merkle == new.merkle && old.kind == new.kind {
+        return;
+    }
+    if !same_shell(old, new) {
+        ops.push(Op::Replace {
+            path: path.clone(),
+            node: new,
+        });
+        return;
+    }
+    match (&old.children, &new.children) {
+        (Some(old), Some(new)) => diff_children(old, new, path, ops),
+        _ => {
+            if old.text != new.text {
+                ops.push(Op::UpdateText {
+                    path: path.clone(),
+                    text: new.text.as_deref().unwrap_or(""),
+                });
+            }
+        }
+    }
+}
+
+/// Unchanged children at both ends are skipped, the ones in between are
+/// paired up in order and whatever is left over is removed or inserted.
+fn diff_children<'a>(old: &[N], new: &'a [N], path: &mut Vec<usize>, ops: &mut Vec<Op<'a>>) {
+    let same = |a: &N, b: &N| a.merkle == b.merkle && a.kind == b.kind;
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+    let paired = old.len().min(new.len());
+    for (i, (a, b)) in old.iter().zip(new).enumerate() {
+        path.push(prefix + i);
+        diff_node(a, b, path, ops);
+        path.pop();
+    }
+    // Removing at the same index drops the following ones in turn
+    for _ in paired..old.len() {
+        path.push(prefix + paired);
+        ops.push(Op::Remove { path: path.clone() });
+        path.pop();
+    }
+    for (i, node) in new.iter().enumerate().skip(paired) {
+        path.push(prefix + i);
+        ops.push(Op::Insert {
+            path: path.clone(),
+            node,
+        });
+        path.pop();
+    }
+}
+
+/// Whether a node can be patched in place, which needs everything but its
+/// text and children to be the same. Wiki links are always replaced since
+/// their target is only part of the merkle hash.
+fn same_shell(old: &N, new: &N) -> bool {
+    let attributes = |a: &A, b: &A| a.id == b.id && a.classes == b.classes && a.pairs == b.pairs;
+    old.kind == new.kind
+        && old.children.is_some() == new.children.is_some()
+        && old.loose == new.loose
+        && old.link.is_none()
+        && new.link.is_none()
+        && old.html == new.html
+        && match (&old.attributes, &new.attributes) {
+            (Some(a), Some(b)) => attributes(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::json::tree;
+    use crate::markdown::{parse, Options};
+    use serde_json::{json, Value};
+
+    fn n(source: &str) -> N {
+        tree(source, &parse(source, &Options::default()), None)
+    }
+
+    /// Applies the patch to the JSON of the old tree.
+    fn apply(old: &N, ops: &[Op]) -> Value {
+        let mut root = serde_json::to_value(old).unwrap();
+        for op in ops {
+            let value = serde_json::to_value(op).unwrap();
+            let path: Vec<usize> = serde_json::from_value(value["path"].clone()).unwrap();
+            let (last, parents) = match path.split_last() {
+                Some((last, parents)) => (*last, parents),
+                None => {
+                    root = value["node"].clone();
+                    continue;
+                }
+            };
+            let mut parent = &mut root;
+            for &i in parents {
+                parent = &mut parent["children"][i];
+            }
+            let children = parent["children"].as_array_mut().unwrap();
+            match op {
+                Op::Insert { .. } => children.insert(last, value["node"].clone()),
+                Op::Remove { .. } => {
+                    children.remove(last);
+                }
+                Op::Replace { .. } => children[last] = value["node"].clone(),
+                Op::UpdateText { .. } => children[last]["text"] = value["text"].clone(),
+            }
+        }
+        shape(root)
+    }
+
+    /// Drops what only tracks where a node is, which the patch leaves alone.
+    fn shape(value: Value) -> Value {
+        match value {
+            Value::Object(o) => o
+                .into_iter()
+                .filter(|(k, _)| {
+                    !matches!(
+                        &k[..],
+                        "span" | "merkle" | "position" | "target" | "anchor" | "label"
+                    )
+                })
+                .map(|(k, v)| (k, shape(v)))
+                .collect(),
+            Value::Array(a) => a.into_iter().map(shape).collect(),
+            v => v,
+        }
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let source = "# A\n\n* b\n* c\n\n[[d]] e";
+        assert!(diff(&n(source), &n(source)).is_empty());
+    }
+
+    #[test]
+    fn test_update_text() {
+        let (old, new) = (n("# A\n\nSome text"), n("# A\n\nSome test"));
+        assert_eq!(
+            serde_json::to_value(diff(&old, &new)).unwrap(),
+            json!([{ "op": "update_text", "path": [2, 0], "text": "Some test" }])
+        );
+    }
+
+    #[test]
+    fn test_moved() {
+        let (old, new) = (n("a\n\n* b\n* c"), n("x\n\na\n\n* b\n* c"));
+        let ops = diff(&old, &new);
+        assert!(
+            matches!(&ops[..], [Op::Insert { path: a, .. }, Op::Insert { path: b, .. }]
+                if a == &[0] && b == &[1]),
+            "{:?}",
+            ops
+        );
+    }
+
+    #[test]
+    fn test_insert_remove_replace() {
+        let (old, new) = (n("a\n\nb"), n("a\n\nb\n\nc"));
+        let ops = diff(&old, &new);
+        assert!(matches!(&ops[..], [.., Op::Insert { path, .. }] if path == &[4]));
+
+        let ops = diff(&new, &old);
+        assert!(matches!(&ops[..], [.., Op::Remove { path }] if path == &[3]));
+
+        let (old, new) = (n("# a"), n("## a"));
+        assert!(matches!(&diff(&old, &new)[..], [Op::Replace { path, .. }] if path == &[0]));
+    }
+
+    #[test]
+    fn test_apply() {
+        let sources = [
+            "",
+            "a",
+            "# A\n\nb",
+            "b\n\n# A",
+            "* a\n* b\n\n> c\n> d",
+            "* a\n* b\n* c\n\n> c",
+            "* a\n\n  b\n* c",
+            "x [[Page|y]] *z* ~~w~~",
+            "x [[Other|y]] *z*",
+            "# A {#a .b}\n\ntext",
+            "# A {#b}\n\ntext\n\nmore",
+        ];
+        for old in &sources {
+            for new in &sources {
+                let (old, new) = (n(old), n(new));
+                let expected = shape(serde_json::to_value(&new).unwrap());
+                assert_eq!(apply(&old, &diff(&old, &new)), expected);
+            }
+        }
+    }
+}
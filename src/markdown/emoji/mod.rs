@@ -0,0 +1,62 @@
+mod shortcodes;
+
+use serde::Serialize;
+
+use shortcodes::SHORTCODES;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Completion {
+    pub shortcode: &'static str,
+    pub emoji: &'static str,
+}
+
+/// Looks up the emoji for a shortcode given without its colons.
+pub fn lookup(shortcode: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by_key(&shortcode, |(s, _)| s)
+        .ok()
+        .map(|i| SHORTCODES[i].1)
+}
+
+/// Every shortcode starting with `prefix`, in alphabetical order.
+pub fn completions(prefix: &str) -> Vec<Completion> {
+    let start = SHORTCODES.partition_point(|(s, _)| *s < prefix);
+    SHORTCODES[start..]
+        .iter()
+        .take_while(|(s, _)| s.starts_with(prefix))
+        .map(|&(shortcode, emoji)| Completion { shortcode, emoji })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("tada"), Some("\u{1f389}"));
+        assert_eq!(lookup("+1"), Some("\u{1f44d}"));
+        assert_eq!(lookup("not_an_emoji"), None);
+        assert_eq!(lookup(""), None);
+    }
+
+    #[test]
+    fn test_completions() {
+        let result = completions("thumbs");
+        assert_eq!(
+            result,
+            vec![
+                Completion {
+                    shortcode: "thumbsdown",
+                    emoji: "\u{1f44e}",
+                },
+                Completion {
+                    shortcode: "thumbsup",
+                    emoji: "\u{1f44d}",
+                },
+            ]
+        );
+        assert!(completions("zzzz").is_empty());
+        assert_eq!(completions("").len(), SHORTCODES.len());
+    }
+}
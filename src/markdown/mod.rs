@@ -1,11 +1,28 @@
+pub mod diff;
+pub mod emoji;
 mod parse;
 mod render;
+pub mod review;
+pub mod tags;
 
-pub use parse::md_parser;
-pub use parse::{Kind, Node};
+pub use parse::{Attributes, Html, Kind, Node, Options};
 
-pub fn parse(source: &str) -> Node {
-    parse::parse(source)
+pub fn parse(source: &str, options: &Options) -> Node {
+    parse::parse(source, options)
 }
 
-pub use render::json;
+/// Updates `doc` after `start..end` of its source was replaced with
+/// `inserted` bytes, giving `source`. Only the blocks around the edit are
+/// parsed again.
+pub fn reparse(
+    source: &str,
+    doc: Node,
+    (start, end): (usize, usize),
+    inserted: usize,
+    options: &Options,
+) -> Node {
+    parse::reparse(source, doc, (start, end), inserted, options)
+}
+
+pub use render::{ansi, binary, html, js, json, latex, markdown, text};
+pub use render::{render_with, Renderer, Resolution, Resolver};
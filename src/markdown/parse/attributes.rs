@@ -0,0 +1,120 @@
+use crate::markdown::parse::Attributes;
+
+/// Parses the inside of a `{#id .class key=value}` block starting at
+/// `start`. Every item must be well formed, otherwise the braces are
+/// treated as text.
+pub fn parse(source: &str, start: usize, end: usize) -> Option<Attributes> {
+    let mut attributes = Attributes {
+        span: (start - 1, end + 1),
+        id: None,
+        classes: vec![],
+        pairs: vec![],
+    };
+    let mut p = start;
+    loop {
+        p += source[p..end].len() - source[p..end].trim_start().len();
+        if p == end {
+            break;
+        }
+        let item = &source[p..end];
+        let len = if let Some(rest) = item.strip_prefix('#') {
+            let n = name(rest);
+            if n == 0 || attributes.id.is_some() {
+                return None;
+            }
+            attributes.id = Some((p + 1, p + 1 + n));
+            1 + n
+        } else if let Some(rest) = item.strip_prefix('.') {
+            let n = name(rest);
+            if n == 0 {
+                return None;
+            }
+            attributes.classes.push((p + 1, p + 1 + n));
+            1 + n
+        } else {
+            let key = key(item);
+            let rest = item[key..].strip_prefix('=')?;
+            let v = p + key + 1;
+            let (value, len) = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let n = rest[1..].find(quote)?;
+                    ((v + 1, v + 1 + n), n + 2)
+                }
+                _ => {
+                    let n = name(rest);
+                    ((v, v + n), n)
+                }
+            };
+            if key == 0 || len == 0 {
+                return None;
+            }
+            attributes.pairs.push(((p, p + key), value));
+            key + 1 + len
+        };
+        p += len;
+        // Items are separated by whitespace
+        if p < end && !source[p..].starts_with(char::is_whitespace) {
+            return None;
+        }
+    }
+    match attributes.id.is_some() || !attributes.classes.is_empty() || !attributes.pairs.is_empty()
+    {
+        true => Some(attributes),
+        false => None,
+    }
+}
+
+fn name(s: &str) -> usize {
+    s.find(|c: char| c.is_whitespace() || "{}\"'=".contains(c))
+        .unwrap_or(s.len())
+}
+
+fn key(s: &str) -> usize {
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    s.find(|c: char| !(c.is_ascii_alphanumeric() || "_.:-".contains(c)))
+        .unwrap_or(s.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attributes(source: &str) -> Option<Attributes> {
+        parse(source, 1, source.len() - 1)
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            attributes("{#intro .wide .dark data-x=1 title=\"A b\"}"),
+            Some(Attributes {
+                span: (0, 41),
+                id: Some((2, 7)),
+                classes: vec![(9, 13), (15, 19)],
+                pairs: vec![((20, 26), (27, 28)), ((29, 34), (36, 39))],
+            })
+        );
+        assert_eq!(
+            attributes("{ .a }"),
+            Some(Attributes {
+                span: (0, 6),
+                id: None,
+                classes: vec![(3, 4)],
+                pairs: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_not_attributes() {
+        assert_eq!(attributes("{}"), None);
+        assert_eq!(attributes("{ }"), None);
+        assert_eq!(attributes("{#a #b}"), None);
+        assert_eq!(attributes("{a b}"), None);
+        assert_eq!(attributes("{x=\"open}"), None);
+        assert_eq!(attributes("{.a.b=c}"), None);
+        assert_eq!(attributes("{#}"), None);
+    }
+}
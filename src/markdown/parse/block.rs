@@ -0,0 +1,718 @@
+use std::mem;
+
+use crate::markdown::parse::token::{Span, Token};
+use crate::markdown::parse::{md_parser, Kind, Node, Options};
+
+/// Parses the tokens in two passes. The first goes over the lines once,
+/// matching each against the block quotes and list items still open to
+/// find where it belongs, and collects the tokens of paragraphs and
+/// headings. The second parses those tokens into inline nodes.
+pub fn parse(tokens: &[Token], source: &str, options: &Options) -> Node {
+    let mut levels = vec![Level::default()];
+    let mut leaves = vec![];
+    for tokens in tokens.split_inclusive(|t| matches!(t, Token::Newline(..))) {
+        feed(
+            &mut levels,
+            &mut leaves,
+            Line {
+                first: None,
+                tokens,
+            },
+        );
+    }
+    close(&mut levels, &mut leaves, 0);
+    let children = levels.pop().unwrap().finish(&mut leaves);
+    let mut doc = Node::new_block(Kind::Document, 0, 0, children);
+    inlines(&mut doc, leaves, source, options);
+    doc.span.1 = doc.children.last().map(|n| n.span.1).unwrap_or(0);
+    doc
+}
+
+/// The tokens of a leaf block, kept for the second pass while the first
+/// leaves an empty node in its place.
+enum Leaf {
+    Paragraph(Vec<Token>),
+    /// The tokens of the line without its newline, and where the blank
+    /// lines after it end
+    Heading(Vec<Token>, Option<usize>),
+    /// The level and the tokens of the paragraph above the underline
+    Setext(usize, Vec<Token>),
+}
+
+/// Replaces the leaf blocks in the tree with their parsed nodes. The
+/// leaves were found in document order, so they are matched up walking
+/// the tree in that order, without recursing into deeply nested
+/// containers.
+fn inlines(doc: &mut Node, leaves: Vec<Leaf>, source: &str, options: &Options) {
+    let mut leaves = leaves.into_iter();
+    let mut stack = vec![doc];
+    while let Some(node) = stack.pop() {
+        if !matches!(node.kind, Kind::Paragraph | Kind::Heading(_)) {
+            stack.extend(node.children.iter_mut().rev());
+            continue;
+        }
+        let text = |kind, tokens: Vec<Token>| {
+            let a = md_parser::inline_content(&tokens, source, options).unwrap();
+            let (start, _) = a.first().unwrap().span;
+            let (_, end) = a.last().unwrap().span;
+            Node::new_block(kind, start, end, a)
+        };
+        *node = match leaves.next().unwrap() {
+            Leaf::Paragraph(tokens) => text(Kind::Paragraph, tokens),
+            Leaf::Heading(tokens, empty) => {
+                md_parser::heading(&tokens, source, options, empty).unwrap()
+            }
+            Leaf::Setext(level, tokens) => text(Kind::Heading(level), tokens),
+        };
+    }
+}
+
+/// A line as seen from inside the containers around it. Their markers are
+/// cut off the front, and `first` stands in for the first token when only
+/// part of its indentation is left.
+#[derive(Clone, Copy)]
+struct Line<'t> {
+    first: Option<Token>,
+    tokens: &'t [Token],
+}
+
+impl<'t> Line<'t> {
+    fn get(&self, i: usize) -> Option<Token> {
+        match (i, self.first) {
+            (0, Some(t)) => Some(t),
+            _ => self.tokens.get(i).copied(),
+        }
+    }
+
+    /// The line from token `i` on.
+    fn skip(self, i: usize) -> Self {
+        match i {
+            0 => self,
+            i => Line {
+                first: None,
+                tokens: self.tokens.get(i..).unwrap_or_default(),
+            },
+        }
+    }
+
+    fn with_first(self, token: Token) -> Self {
+        Line {
+            first: Some(token),
+            ..self
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn end(&self) -> usize {
+        self.tokens.last().map(|t| t.span().1).unwrap_or(0)
+    }
+
+    fn newline(&self) -> Option<Span> {
+        match self.tokens.last() {
+            Some(Token::Newline(s)) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// The tokens without the newline.
+    fn content(self) -> impl Iterator<Item = Token> + 't {
+        let n = self.tokens.len() - self.newline().is_some() as usize;
+        (0..n).filter_map(move |i| self.get(i))
+    }
+
+    fn is_blank(&self) -> bool {
+        matches!(
+            (self.get(0), self.get(1)),
+            (Some(Token::Newline(..)), _) | (Some(Token::Whitespace(..)), Some(Token::Newline(..)))
+        )
+    }
+
+    /// Indentation of up to three spaces, and the token after it.
+    fn indent(&self) -> (Option<Span>, usize) {
+        match self.get(0) {
+            Some(Token::Whitespace((s, e))) if e - s < 4 => (Some((s, e)), 1),
+            _ => (None, 0),
+        }
+    }
+
+    /// The `>` starting a block quote line and the token after it.
+    fn quote_marker(&self) -> Option<(Span, usize)> {
+        let (_, i) = self.indent();
+        match self.get(i) {
+            Some(Token::RightCaret(span)) => Some((span, i + 1)),
+            _ => None,
+        }
+    }
+
+    /// Whether the marker is a number, the width of the marker and its
+    /// indentation, and the token after it.
+    fn list_marker(&self) -> Option<(bool, usize, usize)> {
+        let (indent, i) = self.indent();
+        let (ordered, (start, _)) = marker(self.get(i)?)?;
+        match self.get(i + 1)? {
+            Token::Whitespace((_, end)) => {
+                Some((ordered, end - indent.map_or(start, |s| s.0), i + 2))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the line starts with a run of at most six `#` followed by
+    /// whitespace or the end of the line, so `#tag` stays text.
+    fn is_heading(&self) -> bool {
+        matches!(self.get(0), Some(Token::Hash((s, e))) if e - s <= 6)
+            && matches!(
+                self.get(1),
+                None | Some(Token::Whitespace(..) | Token::Newline(..))
+            )
+    }
+
+    /// The level of the heading when the line underlines a paragraph with
+    /// `=` or `-`.
+    fn setext_underline(&self) -> Option<usize> {
+        let (_, mut i) = self.indent();
+        let level = match self.get(i)? {
+            Token::Equals(..) => 1,
+            Token::Dash(..) => 2,
+            _ => return None,
+        };
+        i += 1;
+        // Dashes are a token each
+        while level == 2 && matches!(self.get(i), Some(Token::Dash(..))) {
+            i += 1;
+        }
+        i += matches!(self.get(i), Some(Token::Whitespace(..))) as usize;
+        matches!(self.get(i), None | Some(Token::Newline(..))).then_some(level)
+    }
+
+    /// Whether the line ends a paragraph before it.
+    fn interrupts(&self) -> bool {
+        self.is_blank()
+            || self.quote_marker().is_some()
+            || self.is_heading()
+            || self.list_marker().is_some()
+    }
+
+    /// The span of the first line of a block quote and the rest of it,
+    /// which is empty for a marker at the end of the input.
+    fn quote_start(self) -> Option<(Span, Self)> {
+        let ((start, _), i) = self.quote_marker()?;
+        let i = i + matches!(self.get(i), Some(Token::Whitespace(..))) as usize;
+        let rest = self.skip(i);
+        let end = match rest.is_empty() {
+            true => self.end(),
+            false => rest.end(),
+        };
+        Some(((start, end), rest))
+    }
+
+    /// The rest of a line in a block quote, which is empty when nothing
+    /// follows the marker. Lines without one only continue the quote when
+    /// they are `lazy` paragraph text.
+    fn quote_continuation(self, lazy: bool) -> Option<Self> {
+        match self.quote_marker() {
+            Some((_, i)) => Some(match self.get(i) {
+                // Only one space belongs to the marker
+                Some(Token::Whitespace((s, e))) if e - s > 1 && !self.skip(i + 1).is_empty() => {
+                    self.skip(i).with_first(Token::Whitespace((s + 1, e)))
+                }
+                Some(Token::Whitespace(..)) => self.skip(i + 1),
+                _ => self.skip(i),
+            }),
+            None if lazy => {
+                let (_, i) = self.indent();
+                let rest = self.skip(i);
+                (!rest.is_empty()).then_some(rest)
+            }
+            None => None,
+        }
+    }
+
+    /// The first line of a list item after its marker.
+    fn list_start(self) -> Option<(bool, usize, Self)> {
+        let (ordered, width, i) = self.list_marker()?;
+        let rest = self.skip(i);
+        (!rest.is_empty()).then_some((ordered, width, rest))
+    }
+
+    /// The rest of a line in a list item, which needs to be indented past
+    /// the marker unless it is `lazy` paragraph text.
+    fn item_continuation(self, width: usize, lazy: bool) -> Option<Self> {
+        match self.get(0)? {
+            Token::Whitespace((s, e)) if e - s >= width && !self.skip(1).is_empty() => {
+                Some(match e - s == width {
+                    true => self.skip(1),
+                    false => self.with_first(Token::Whitespace((s + width, e))),
+                })
+            }
+            _ if lazy => Some(self),
+            _ => None,
+        }
+    }
+
+    fn empty_line(&self) -> Node {
+        let (start, _) = self.get(0).unwrap().span();
+        Node::new(Kind::EmptyLine, start, self.end())
+    }
+}
+
+/// Whether a list marker is a number, and its span.
+fn marker(token: Token) -> Option<(bool, Span)> {
+    match token {
+        Token::Plus(s) | Token::Asterisk(s) | Token::Dash(s) => Some((false, s)),
+        Token::NumDot(s) | Token::NumParen(s) => Some((true, s)),
+        _ => None,
+    }
+}
+
+/// The blocks in the document or in one container.
+#[derive(Default)]
+struct Level {
+    children: Vec<Node>,
+    /// Blank lines not given to a block yet
+    blanks: Vec<Node>,
+    open: Open,
+}
+
+#[derive(Default)]
+enum Open {
+    #[default]
+    Nothing,
+    /// The tokens so far and the newline ending the last line
+    Paragraph(Vec<Token>, Option<Span>),
+    Quote(Span),
+    /// The items so far, and the marker width and span of the open one
+    List {
+        ordered: bool,
+        loose: bool,
+        items: Vec<Node>,
+        width: usize,
+        item: Span,
+    },
+}
+
+impl Level {
+    /// Turns the blank lines so far into a block.
+    fn flush(&mut self, leaves: &mut [Leaf]) {
+        let (Some(first), Some(last)) = (self.blanks.first(), self.blanks.last()) else {
+            return;
+        };
+        let (start, end) = (first.span.0, last.span.1);
+        // Headings without text end with the blank lines after them
+        let heading = matches!(self.children.last(), Some(n) if matches!(n.kind, Kind::Heading(_)));
+        if let (true, Some(Leaf::Heading(_, empty))) = (heading, leaves.last_mut()) {
+            *empty = Some(end);
+        }
+        let blanks = mem::take(&mut self.blanks);
+        let empty = Node::new_block(Kind::Empty, start, end, blanks);
+        self.children.push(empty);
+    }
+
+    /// Turns the paragraph into a heading underlined by the line. Like
+    /// after other headings the newline ending it counts as a blank line.
+    fn end_setext(&mut self, leaves: &mut Vec<Leaf>, level: usize, line: &Line) {
+        if let Open::Paragraph(tokens, _) = mem::take(&mut self.open) {
+            self.children.push(Node::new(Kind::Heading(level), 0, 0));
+            leaves.push(Leaf::Setext(level, tokens));
+            if let Some((start, end)) = line.newline() {
+                self.blanks.push(Node::new(Kind::EmptyLine, start, end));
+            }
+        }
+    }
+
+    /// Ends the paragraph. Its last newline counts as a blank line unless
+    /// nothing follows in the container.
+    fn end_paragraph(&mut self, leaves: &mut Vec<Leaf>, last: bool) {
+        if let Open::Paragraph(tokens, newline) = mem::take(&mut self.open) {
+            self.children.push(Node::new(Kind::Paragraph, 0, 0));
+            leaves.push(Leaf::Paragraph(tokens));
+            if let (Some((start, end)), false) = (newline, last) {
+                self.blanks.push(Node::new(Kind::EmptyLine, start, end));
+            }
+        }
+    }
+
+    /// Takes the blank lines after a list item into it.
+    fn trail(&mut self) {
+        let Open::List { items, .. } = &mut self.open else {
+            return;
+        };
+        if let (Some(item), Some(first), Some(last)) =
+            (items.last_mut(), self.blanks.first(), self.blanks.last())
+        {
+            let (start, end) = (first.span.0, last.span.1);
+            item.span.1 = end;
+            let blanks = mem::take(&mut self.blanks);
+            item.children
+                .push(Node::new_block(Kind::Empty, start, end, blanks));
+        }
+    }
+
+    fn end_item(&mut self, content: Vec<Node>) {
+        if let Open::List { items, item, .. } = &mut self.open {
+            let (start, end) = *item;
+            items.push(Node::new_block(Kind::ListItem, start, end, content));
+        }
+    }
+
+    /// Starts another item in the open list. Blank lines before it make
+    /// the list loose.
+    fn next_item(&mut self, width: usize, span: Span) {
+        if !self.blanks.is_empty() {
+            if let Open::List { loose, .. } = &mut self.open {
+                *loose = true;
+            }
+            self.trail();
+        }
+        if let Open::List { width: w, item, .. } = &mut self.open {
+            (*w, *item) = (width, span);
+        }
+    }
+
+    /// Ends the open list. Blank lines after a tight list are left to the
+    /// container, a loose one takes them into its last item.
+    fn end_list(&mut self) {
+        if let Open::List { loose: true, .. } = self.open {
+            self.trail();
+        }
+        if let Open::List {
+            ordered,
+            loose,
+            items,
+            ..
+        } = mem::take(&mut self.open)
+        {
+            let (start, _) = items.first().unwrap().span;
+            let (_, end) = items.last().unwrap().span;
+            let kind = match ordered {
+                false => Kind::UnorderedList(loose),
+                true => Kind::OrderedList(loose),
+            };
+            self.children.push(Node::new_block(kind, start, end, items));
+        }
+    }
+
+    /// Ends the open container with the blocks inside it.
+    fn end_container(&mut self, content: Vec<Node>) {
+        match self.open {
+            Open::Quote((start, end)) => {
+                self.open = Open::Nothing;
+                self.children
+                    .push(Node::new_block(Kind::BlockQuote, start, end, content));
+            }
+            Open::List { .. } => {
+                self.end_item(content);
+                self.end_list();
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self, leaves: &mut Vec<Leaf>) -> Vec<Node> {
+        self.end_paragraph(leaves, true);
+        self.flush(leaves);
+        self.children
+    }
+}
+
+/// Ends the containers open in level `i` and below.
+fn close(levels: &mut Vec<Level>, leaves: &mut Vec<Leaf>, i: usize) {
+    while levels.len() > i + 1 {
+        let content = levels.pop().unwrap().finish(leaves);
+        levels.last_mut().unwrap().end_container(content);
+    }
+}
+
+/// Passes the line through the open containers it continues, and starts
+/// blocks with what is left in the innermost one.
+fn feed(levels: &mut Vec<Level>, leaves: &mut Vec<Leaf>, mut line: Line) {
+    let mut i = 0;
+    while i + 1 < levels.len() {
+        // Only paragraph text continues without the containers' markers,
+        // an underline needs them to turn the paragraph into a heading
+        let lazy = matches!(levels.last().unwrap().open, Open::Paragraph(..))
+            && !line.interrupts()
+            && line.setext_underline().is_none();
+        let mut after_blank = false;
+        let Level { open, blanks, .. } = &mut levels[i];
+        let blank = line.is_blank();
+        let rest = match open {
+            Open::Quote(_) if blank => None,
+            // A marker with nothing after it still belongs to the quote
+            Open::Quote(span) => line.quote_continuation(lazy).inspect(|rest| {
+                span.1 = match rest.is_empty() {
+                    true => line.end(),
+                    false => rest.end(),
+                };
+            }),
+            Open::List { .. } if blank => {
+                blanks.push(line.empty_line());
+                return;
+            }
+            Open::List {
+                width, loose, item, ..
+            } => line
+                .item_continuation(*width, lazy && blanks.is_empty())
+                .inspect(|rest| {
+                    // Blank lines inside an item are dropped
+                    if !blanks.is_empty() {
+                        blanks.clear();
+                        *loose = true;
+                        after_blank = true;
+                    }
+                    item.1 = rest.end();
+                }),
+            _ => unreachable!("levels below one without a container"),
+        };
+        // but still end the paragraph before them
+        if after_blank {
+            levels.last_mut().unwrap().end_paragraph(leaves, false);
+        }
+        match rest {
+            Some(rest) if rest.is_empty() => return,
+            Some(rest) => {
+                line = rest;
+                i += 1;
+            }
+            None => {
+                close(levels, leaves, i + 1);
+                let content = levels.pop().unwrap().finish(leaves);
+                let level = &mut levels[i];
+                if !matches!(level.open, Open::List { .. }) {
+                    level.end_container(content);
+                    break;
+                }
+                level.end_item(content);
+                match line.list_start() {
+                    Some((_, width, rest)) => {
+                        level.next_item(width, (rest.get(0).unwrap().span().0, rest.end()));
+                        levels.push(Level::default());
+                        line = rest;
+                        i += 1;
+                    }
+                    None => {
+                        // A marker with nothing after it still makes the
+                        // list loose
+                        if let (Open::List { loose, .. }, Some(_)) =
+                            (&mut level.open, line.list_marker())
+                        {
+                            *loose = true;
+                        }
+                        level.end_list();
+                    }
+                }
+                break;
+            }
+        }
+    }
+    start(levels, leaves, i, line);
+}
+
+/// Adds the line to level `i`, which has no container open.
+fn start(levels: &mut Vec<Level>, leaves: &mut Vec<Leaf>, mut i: usize, mut line: Line) {
+    loop {
+        let level = &mut levels[i];
+        if let Open::Paragraph(tokens, newline) = &mut level.open {
+            if let Some(heading) = line.setext_underline() {
+                level.end_setext(leaves, heading, &line);
+                return;
+            }
+            if !line.interrupts() {
+                tokens.extend(newline.map(Token::Newline));
+                tokens.extend(line.content());
+                *newline = line.newline();
+                return;
+            }
+            level.end_paragraph(leaves, false);
+        }
+        if line.is_blank() {
+            level.blanks.push(line.empty_line());
+            return;
+        }
+        level.flush(leaves);
+        if line.is_heading() {
+            level.children.push(Node::new(Kind::Heading(0), 0, 0));
+            leaves.push(Leaf::Heading(line.content().collect(), None));
+            if let Some((start, end)) = line.newline() {
+                level.blanks.push(Node::new(Kind::EmptyLine, start, end));
+            }
+            return;
+        }
+        let rest = if let Some((span, rest)) = line.quote_start() {
+            level.open = Open::Quote(span);
+            rest
+        } else if let Some((ordered, width, rest)) = line.list_start() {
+            level.open = Open::List {
+                ordered,
+                loose: false,
+                items: vec![],
+                width,
+                item: (rest.get(0).unwrap().span().0, rest.end()),
+            };
+            rest
+        } else {
+            level.open = Open::Paragraph(line.content().collect(), line.newline());
+            return;
+        };
+        levels.push(Level::default());
+        if rest.is_empty() {
+            return;
+        }
+        line = rest;
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate test;
+
+    use super::*;
+    use crate::markdown::parse::parse;
+    use test::Bencher;
+
+    /// The number of containers on the most deeply nested path.
+    fn depth(node: &Node) -> usize {
+        let container = matches!(
+            node.kind,
+            Kind::BlockQuote | Kind::UnorderedList(_) | Kind::OrderedList(_) | Kind::ListItem
+        );
+        node.children.iter().map(depth).max().unwrap_or(0) + container as usize
+    }
+
+    fn quotes(depth: usize) -> String {
+        format!("{}text\n", "> ".repeat(depth)).repeat(20)
+    }
+
+    fn lists(depth: usize) -> String {
+        (0..depth)
+            .map(|d| format!("{}* item\n", "  ".repeat(d)))
+            .collect()
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let doc = parse(&quotes(1000), &Options::default());
+        assert_eq!(depth(&doc), 1000);
+        // Every level is a list and an item
+        let doc = parse(&lists(200), &Options::default());
+        assert_eq!(depth(&doc), 400);
+    }
+
+    #[test]
+    fn test_quote_end() {
+        let a = || {
+            let text = vec![Node::new(Kind::Plaintext, 2, 3)];
+            Node::new_block(Kind::Paragraph, 2, 3, text)
+        };
+        // A marker with nothing after it on the last line stays in the quote
+        for source in ["> a\n>", "> a\n> "] {
+            let n = source.len();
+            let bq = Node::new_block(Kind::BlockQuote, 0, n, vec![a()]);
+            let doc = Node::new_block(Kind::Document, 0, n, vec![bq]);
+            assert_eq!(parse(source, &Options::default()), doc, "{:?}", source);
+        }
+        // So does one at the end of the input, even without anything before
+        for source in [">", "> "] {
+            let n = source.len();
+            let bq = Node::new(Kind::BlockQuote, 0, n);
+            let doc = Node::new_block(Kind::Document, 0, n, vec![bq]);
+            assert_eq!(parse(source, &Options::default()), doc, "{:?}", source);
+        }
+        // A last line of only whitespace follows the quote instead of
+        // turning it into a paragraph
+        let bq = Node::new_block(Kind::BlockQuote, 0, 4, vec![a()]);
+        let space = vec![Node::new(Kind::Plaintext, 4, 6)];
+        let p = Node::new_block(Kind::Paragraph, 4, 6, space);
+        let doc = Node::new_block(Kind::Document, 0, 6, vec![bq, p]);
+        assert_eq!(parse("> a\n  ", &Options::default()), doc);
+    }
+
+    /// The kinds of the blocks in the document.
+    fn blocks(source: &str) -> Vec<Kind> {
+        let doc = parse(source, &Options::default());
+        doc.children.into_iter().map(|n| n.kind).collect()
+    }
+
+    #[test]
+    fn test_lazy_continuation() {
+        // Paragraph text continues the paragraph inside the containers
+        assert_eq!(blocks("* a\nb"), [Kind::UnorderedList(false)]);
+        assert_eq!(blocks("> a\nb"), [Kind::BlockQuote]);
+        assert_eq!(blocks("> * a\nb"), [Kind::BlockQuote]);
+        // Other blocks end them unless they are indented into the item
+        assert_eq!(
+            blocks("* a\n# H"),
+            [Kind::UnorderedList(false), Kind::Heading(1)]
+        );
+        assert_eq!(blocks("> a\n# H"), [Kind::BlockQuote, Kind::Heading(1)]);
+        assert_eq!(
+            blocks("* a\n> b"),
+            [Kind::UnorderedList(false), Kind::BlockQuote]
+        );
+        assert_eq!(blocks("> # H\nb"), [Kind::BlockQuote, Kind::Paragraph]);
+        let doc = parse("* a\n  # H", &Options::default());
+        let item = &doc.children[0].children[0];
+        assert_eq!(item.children.last().unwrap().kind, Kind::Heading(1));
+    }
+
+    #[test]
+    fn test_item_paragraphs() {
+        // A blank line inside an item ends the paragraph before it
+        let doc = parse("* a\n\n  b", &Options::default());
+        let item = &doc.children[0].children[0];
+        let kinds: Vec<_> = item.children.iter().map(|n| &n.kind).collect();
+        assert_eq!(kinds, [&Kind::Paragraph, &Kind::Empty, &Kind::Paragraph]);
+        assert_eq!(item.children[2].span, (7, 8));
+    }
+
+    #[test]
+    fn test_setext_heading() {
+        let h = |level, end| {
+            let text = vec![Node::new(Kind::Plaintext, 0, end)];
+            Node::new_block(Kind::Heading(level), 0, end, text)
+        };
+        let doc = |n, children| Node::new_block(Kind::Document, 0, n, children);
+        assert_eq!(parse("A\n===", &Options::default()), doc(1, vec![h(1, 1)]));
+        assert_eq!(
+            parse("A\nb\n- ", &Options::default()),
+            doc(3, vec![h(2, 3)])
+        );
+        assert_eq!(
+            blocks("A\n--- \nb"),
+            [Kind::Heading(2), Kind::Empty, Kind::Paragraph]
+        );
+        assert_eq!(blocks("* a\n  ---"), [Kind::UnorderedList(false)]);
+        // Not an underline without text above it, with more after it or
+        // without the containers' markers
+        assert_eq!(blocks("===\n"), [Kind::Paragraph]);
+        assert_eq!(blocks("a\n= ="), [Kind::Paragraph]);
+        assert_eq!(blocks("> a\n==="), [Kind::BlockQuote, Kind::Paragraph]);
+    }
+
+    #[test]
+    fn test_hashtag_line() {
+        // A hash run needs whitespace after it to start a heading
+        assert_eq!(blocks("a\n#tag"), [Kind::Paragraph]);
+        assert_eq!(blocks("* a\n#tag"), [Kind::UnorderedList(false)]);
+        assert_eq!(
+            blocks("a\n#"),
+            [Kind::Paragraph, Kind::Empty, Kind::Heading(1)]
+        );
+    }
+
+    #[bench]
+    fn bench_nested_quotes(b: &mut Bencher) {
+        let source = quotes(200);
+        b.iter(|| parse(&source, &Options::default()));
+    }
+
+    #[bench]
+    fn bench_nested_lists(b: &mut Bencher) {
+        let source = lists(200);
+        b.iter(|| parse(&source, &Options::default()));
+    }
+}
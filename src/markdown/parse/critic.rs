@@ -0,0 +1,56 @@
+/// The CriticMarkup constructs, `arrow` is where `~>` starts.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Critic {
+    Addition,
+    Deletion,
+    Substitution { arrow: usize },
+    Highlight,
+    Comment,
+}
+
+/// Scans CriticMarkup starting at the `{` at `start`. Returns the end of the
+/// construct, the closing marker is the first one after the opening marker.
+pub fn scan(source: &str, start: usize) -> Option<(usize, Critic)> {
+    let s = &source[start..];
+    let (close, critic) = match s.get(..3)? {
+        "{++" => ("++}", Critic::Addition),
+        "{--" => ("--}", Critic::Deletion),
+        "{==" => ("==}", Critic::Highlight),
+        "{>>" => ("<<}", Critic::Comment),
+        "{~~" => {
+            let arrow = s[3..].find("~>")? + 3;
+            let end = s[arrow + 2..].find("~~}")? + arrow + 2 + 3;
+            let arrow = start + arrow;
+            return Some((start + end, Critic::Substitution { arrow }));
+        }
+        _ => return None,
+    };
+    let end = s[3..].find(close)? + 3 + 3;
+    Some((start + end, critic))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        assert_eq!(scan("{++a++}", 0), Some((7, Critic::Addition)));
+        assert_eq!(scan("x {--a\nb--} --}", 2), Some((11, Critic::Deletion)));
+        assert_eq!(scan("{==a==}{>>b<<}", 0), Some((7, Critic::Highlight)));
+        assert_eq!(scan("{==a==}{>>b<<}", 7), Some((14, Critic::Comment)));
+        assert_eq!(scan("{>><<}", 0), Some((6, Critic::Comment)));
+        assert_eq!(
+            scan("{~~a~>b~~}", 0),
+            Some((10, Critic::Substitution { arrow: 4 }))
+        );
+    }
+
+    #[test]
+    fn test_not_critic() {
+        assert_eq!(scan("{++a", 0), None);
+        assert_eq!(scan("{+a+}", 0), None);
+        assert_eq!(scan("{~~a~~}", 0), None);
+        assert_eq!(scan("{", 0), None);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::markdown::parse::Html;
+
+/// Scans raw inline HTML starting at the `<` at `start`, following the
+/// CommonMark rules. Returns the end of the construct and what it is.
+pub fn scan(source: &str, start: usize) -> Option<(usize, Html)> {
+    let s = &source[start..];
+    if let Some(rest) = s.strip_prefix("<!--") {
+        // "<!-->" and "<!--->" are complete (empty) comments
+        let end = match rest {
+            _ if rest.starts_with('>') => 1,
+            _ if rest.starts_with("->") => 2,
+            _ => rest.find("-->")? + 3,
+        };
+        return Some((start + 4 + end, Html::Comment));
+    }
+    if let Some(rest) = s.strip_prefix("<![CDATA[") {
+        let end = rest.find("]]>")? + 3;
+        return Some((start + 9 + end, Html::Cdata));
+    }
+    if let Some(rest) = s.strip_prefix("<!") {
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let end = rest.find('>')? + 1;
+        return Some((start + 2 + end, Html::Declaration));
+    }
+    if let Some(rest) = s.strip_prefix("<?") {
+        let end = rest.find("?>")? + 2;
+        return Some((start + 2 + end, Html::ProcessingInstruction));
+    }
+    if let Some(rest) = s.strip_prefix("</") {
+        let n = tag_name(rest)?;
+        let p = 2 + n + whitespace(&rest[n..]);
+        return match s[p..].starts_with('>') {
+            true => Some((
+                start + p + 1,
+                Html::Close {
+                    name: (start + 2, start + 2 + n),
+                },
+            )),
+            false => None,
+        };
+    }
+    let n = tag_name(s.strip_prefix('<')?)?;
+    let mut p = 1 + n;
+    while let Some(a) = attribute(&s[p..]) {
+        p += a;
+    }
+    p += whitespace(&s[p..]);
+    let self_closing = s[p..].starts_with("/>");
+    match self_closing || s[p..].starts_with('>') {
+        true => Some((
+            start + p + if self_closing { 2 } else { 1 },
+            Html::Open {
+                name: (start + 1, start + 1 + n),
+                self_closing,
+            },
+        )),
+        false => None,
+    }
+}
+
+fn tag_name(s: &str) -> Option<usize> {
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(
+        s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(s.len()),
+    )
+}
+
+/// Spaces, tabs and at most one line ending.
+fn whitespace(s: &str) -> usize {
+    let mut newline = false;
+    s.find(|c: char| match c {
+        ' ' | '\t' => false,
+        '\n' if !newline => {
+            newline = true;
+            false
+        }
+        _ => true,
+    })
+    .unwrap_or(s.len())
+}
+
+/// Matches whitespace followed by an attribute and its optional value.
+fn attribute(s: &str) -> Option<usize> {
+    let mut p = whitespace(s);
+    if p == 0 || !s[p..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == ':') {
+        return None;
+    }
+    p += s[p..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "_.:-".contains(c)))
+        .unwrap_or(s.len() - p);
+    let q = p + whitespace(&s[p..]);
+    if !s[q..].starts_with('=') {
+        return Some(p);
+    }
+    let q = q + 1 + whitespace(&s[q + 1..]);
+    let value = match s[q..].chars().next()? {
+        quote @ ('"' | '\'') => s[q + 1..].find(quote)? + 2,
+        _ => s[q..]
+            .find(|c: char| c.is_whitespace() || "\"'=<>`".contains(c))
+            .unwrap_or(s.len() - q),
+    };
+    match value {
+        0 => None,
+        _ => Some(q + value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(
+            scan("<a href=\"x>y\" title='t' data-x=1 hidden>", 0),
+            Some((
+                40,
+                Html::Open {
+                    name: (1, 2),
+                    self_closing: false,
+                }
+            ))
+        );
+        assert_eq!(
+            scan("x <br/>", 2),
+            Some((
+                7,
+                Html::Open {
+                    name: (3, 5),
+                    self_closing: true,
+                }
+            ))
+        );
+        assert_eq!(scan("</span >", 0), Some((8, Html::Close { name: (2, 6) })));
+        assert_eq!(scan("<a\nb>", 0).map(|(end, _)| end), Some(5));
+        assert_eq!(scan("<33>", 0), None);
+        assert_eq!(scan("<a b=>", 0), None);
+        assert_eq!(scan("<a href=\"x>", 0), None);
+        assert_eq!(scan("</a b>", 0), None);
+    }
+
+    #[test]
+    fn test_other() {
+        assert_eq!(scan("<!-- a -- b -->", 0), Some((15, Html::Comment)));
+        assert_eq!(scan("<!-->", 0), Some((5, Html::Comment)));
+        assert_eq!(
+            scan("<?php echo 1; ?>", 0),
+            Some((16, Html::ProcessingInstruction))
+        );
+        assert_eq!(scan("<!DOCTYPE html>", 0), Some((15, Html::Declaration)));
+        assert_eq!(scan("<![CDATA[<&>]]>", 0), Some((15, Html::Cdata)));
+        assert_eq!(scan("<!-- open", 0), None);
+        assert_eq!(scan("<! x>", 0), None);
+    }
+}
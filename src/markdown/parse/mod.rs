@@ -1,9 +1,19 @@
+// The generated rule functions take the grammar arguments on top of their own
+#![allow(clippy::too_many_arguments)]
+
+pub(crate) mod attributes;
+mod block;
+mod critic;
+mod html;
+mod reparse;
 mod token;
 
-use peg;
+use crate::markdown::emoji;
+use critic::Critic;
+pub use reparse::reparse;
 use token::{Span, Token, Tokenizer};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Kind {
     // Container block tokens
     Document,
@@ -19,13 +29,80 @@ pub enum Kind {
     // Inline tokens
     Plaintext,
     Whitespace,
+    Strikethrough,
+    MathInline,
+    WikiLink {
+        embed: bool,
+        target: (usize, usize),
+        anchor: Option<(usize, usize)>,
+        label: Option<(usize, usize)>,
+    },
+    Mention,
+    Hashtag,
+    Emoji(&'static str),
+    Highlight,
+    Superscript,
+    Subscript,
+    InlineHtml(Html),
+    CriticAddition,
+    CriticDeletion,
+    CriticSubstitution,
+    CriticHighlight,
+    CriticComment,
+    SmartPunctuation(&'static str),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Html {
+    Open {
+        name: (usize, usize),
+        self_closing: bool,
+    },
+    Close {
+        name: (usize, usize),
+    },
+    Comment,
+    ProcessingInstruction,
+    Declaration,
+    Cdata,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Parse TeX math between dollar signs
+    pub math: bool,
+    /// Recognize `@username` mentions
+    pub mentions: bool,
+    /// Recognize `#tag` and nested `#tag/subtag` hashtags
+    pub hashtags: bool,
+    /// Parse `==highlighted==` text
+    pub highlight: bool,
+    /// Parse `^superscript^` text
+    pub superscript: bool,
+    /// Parse `~subscript~` text, strikethrough then needs two tildes
+    pub subscript: bool,
+    /// Parse CriticMarkup additions, deletions, substitutions and comments
+    pub critic_markup: bool,
+    /// Replace straight quotes, `--`, `---` and `...` with typographic ones
+    pub smart_punctuation: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     pub kind: Kind,
     pub span: (usize, usize),
     pub children: Vec<Node>,
+    pub attributes: Option<Attributes>,
+}
+
+/// A `{#id .class key=value}` block. `span` covers the braces, the other
+/// spans point at the names and values inside.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attributes {
+    pub span: (usize, usize),
+    pub id: Option<(usize, usize)>,
+    pub classes: Vec<(usize, usize)>,
+    pub pairs: Vec<((usize, usize), (usize, usize))>,
 }
 
 impl Node {
@@ -34,6 +111,7 @@ impl Node {
             kind,
             span: (start, end),
             children: vec![],
+            attributes: None,
         }
     }
 
@@ -42,336 +120,211 @@ impl Node {
             kind,
             span: (start, end),
             children,
+            attributes: None,
         }
     }
 }
 
 peg::parser! {
     // Adapted from https://github.com/jgm/peg-markdown/blob/master/markdown_parser.leg
-    pub grammar md_parser() for [Token] {
-        pub rule doc() -> Node
-            = a:(b:empty() { vec![b] } / b:block()* { b }) {
-                let children = a.into_iter().flatten().collect::<Vec<_>>();
-                let end = children.last().map(|n| n.span.1).unwrap_or(0);
-                Node::new_block(Kind::Document, 0, end, children)
-            }
-
-        rule block() -> Vec<Node>
-            = a:blank_lines_eof()? b:(
-                c:heading() /
-                c:block_quote() /
-                c:unordered_list() /
-                c:ordered_list() /
-                c:paragraph()
-                { c }
-            ) {
-                match a.flatten() {
-                    Some(a) => {
-                        let mut v = vec![a];
-                        v.extend(b);
-                        v
-                    },
-                    None => b,
-                }
-            }
-
+    pub grammar md_parser(source: &str, options: &Options) for [Token] {
         // Heading
-        rule atx_inline() -> Node
-            = !newline() x:(
-                non_continuing_text()
-            ) { x }
+        //
+        // Takes the tokens of the line without its newline, and where the
+        // blank lines after it end for headings without text.
         rule atx_start() -> Span
             = a:$([Token::Hash((a, b)) if (b - a) <= 6]) { a[0].span() }
-        rule atx_empty() -> Vec<Node>
-            = s:atx_start() t:sp() !atx_inline() b:blank_lines_eof() {
-                let (x, start) = t.unwrap_or(s);
-                let (_, end) = (&b).as_ref().map(|b| b.span).unwrap_or((x, start));
-                let n = Node::new(Kind::Heading(s.1 - s.0), start, end);
-                match b {
-                    Some(b) => vec![n, b],
-                    None => vec![n],
-                }
+        rule atx_empty(empty: Option<usize>) -> Node
+            = s:atx_start() t:sp() {
+                let (_, start) = t.unwrap_or(s);
+                Node::new(Kind::Heading(s.1 - s.0), start, empty.unwrap_or(start))
             }
-        rule atx_heading() -> Vec<Node>
-            = s:atx_start() t:ws() a:atx_inline()* b:blank_lines_eof() {
+        rule atx_attributes() -> Attributes
+            = sp() a:attributes() sp() eof() { a }
+        rule atx_heading(empty: Option<usize>) -> Node
+            = s:atx_start() t:ws()
+              a:$((!atx_attributes() [_])*)
+              c:atx_attributes()? {
+                let a = md_parser::inline_content(a, source, options).unwrap();
                 let (_, x) = t;
-                let (_, y) = (&b).as_ref().map(|b| b.span).unwrap_or(t);
                 let start = a.first().map(|a| a.span.0).unwrap_or(x);
-                let end = a.last().map(|y| y.span.1).unwrap_or(y);
-                let n = Node::new_block(Kind::Heading(s.1 - s.0), start, end, a);
-                match b {
-                    Some(b) => vec![n, b],
-                    None => vec![n],
-                }
+                let end = c.as_ref().map(|c| c.span.1)
+                    .or_else(|| a.last().map(|y| y.span.1))
+                    .or(empty)
+                    .unwrap_or(x);
+                let mut n = Node::new_block(Kind::Heading(s.1 - s.0), start, end, a);
+                n.attributes = c;
+                n
             }
-        rule heading() -> Vec<Node>
-            = h:atx_heading() / h:atx_empty()
+        pub rule heading(empty: Option<usize>) -> Node
+            = h:atx_heading((empty)) / h:atx_empty((empty))
             { h }
 
-        // Block quote
-        rule block_quote_start() -> Vec<Token>
-            =  z:non_indent_space()?
-               x:$([Token::RightCaret(..)] [Token::Whitespace(..)]?) {
-                   Vec::from(x)
-               }
-        rule block_quote() -> Vec<Node>
-            = x:block_quote_start() a:line()
-              b:(
-                non_indent_space()? [Token::RightCaret(..)] b:$([Token::Whitespace(..)])? c:line() { (b, c) } /
-                non_indent_space()? ![Token::RightCaret(..)] !blank_line() c:line() { (None, c) }
-              )*
-              c:blank_lines_eof()
-              {
-                let b = b.into_iter().map(|(b, line)| {
-                    match b {
-                        Some([Token::Whitespace((s, e)), ..]) if *s < e - 1 => {
-                            // Trim off exactly one space character from each line
-                            // leaving the remaining whitespace to be included in
-                            // the parsed body of the block quote
-                            [vec![Token::Whitespace((s + 1, *e))], line].concat()
-                        },
-                        _ => line,
-                    }
-                });
-                let (start, _) = x[0].span();
-                let s = [a, b.flatten().collect()].concat();
-                let (_, end) = s.last().map(|n| n.span()).unwrap_or(x[0].span());
-                let sub = md_parser::doc(&s).unwrap();
-                let bq = Node::new_block(Kind::BlockQuote, start, end, sub.children);
-                match c {
-                    Some(c) => vec![bq, c],
-                    None => vec![bq],
-                }
+        // Inlines
+        //
+        // The block pass decides which tokens belong to a leaf block, the
+        // tokens are then handed to `inline_content` to build the inline nodes.
+        pub rule inline_content() -> Vec<Node>
+            = a:inline()* { split_plaintext(source, options, a) }
+        rule inline() -> Node
+            = special() / text()
+
+        rule text() -> Node
+            = a:$((!special() [_])+) {
+              let (start, _) = a.first().unwrap().span();
+              let (_, end) = a.last().unwrap().span();
+              Node::new(Kind::Plaintext, start, end)
+            }
+        rule special() -> Node
+            = critic() /
+              strikethrough() /
+              subscript() /
+              superscript() /
+              highlight() /
+              math_inline() /
+              wiki_link() /
+              inline_html()
+
+        rule enabled(extension: bool)
+            = {? if extension { Ok(()) } else { Err("extension disabled") } }
+
+        // Delimiter runs
+        //
+        // The opening run must not be followed by whitespace and the closing
+        // run must not be preceded by whitespace. A run of the closing length
+        // that follows whitespace is kept as content instead.
+        rule delimited(close: rule<Span>) -> (Vec<Node>, Span)
+            = a:delimited_body(<close()>) b:close() {
+                (md_parser::inline_content(a, source, options).unwrap(), b)
               }
+        rule delimited_body(close: rule<Span>) -> &'input [Token]
+            = ![Token::Whitespace(..) | Token::Newline(..)]
+              a:$((
+                [Token::Whitespace(..) | Token::Newline(..)] close()? /
+                !close() [_]
+              )+) { a }
 
-        // List
-        rule bullet() -> usize
-            = a:non_indent_space()
-              b:$([Token::Plus(..) | Token::Asterisk(..) | Token::Dash(..)])
-              c:whitespace() {
-                let (a, _) = a.unwrap_or(b[0].span());
-                let (_, c) = c.span;
-                c - a
+        // Strikethrough
+        //
+        // A single tilde is left to subscripts when they are enabled.
+        rule tildes() -> Span
+            = a:$([Token::Tilde((s, e)) if (e - s) == 2 || (e - s) == 1 && !options.subscript]) {
+                a[0].span()
               }
-        rule enumerator() -> usize
-            = a:non_indent_space()
-              b:$([Token::NumDot(..) | Token::NumParen(..)])
-              c:whitespace() {
-                let (a, _) = a.unwrap_or(b[0].span());
-                let (_, c) = c.span;
-                c - a
+        rule tildes_of(n: usize) -> Span
+            = a:$([Token::Tilde((s, e)) if (e - s) == n]) { a[0].span() }
+        rule strikethrough() -> Node
+            = a:tildes() b:delimited(<tildes_of((a.1 - a.0))>) {
+                let (children, (_, end)) = b;
+                Node::new_block(Kind::Strikethrough, a.0, end, children)
               }
-        rule unordered_list() -> Vec<Node>
-            = &bullet()
-              a:(
-                b:list_tight(false, false) /
-                b:list_loose(false, true)
-              ) { a }
-        rule ordered_list() -> Vec<Node>
-            = &enumerator()
-              a:(
-                b:list_tight(true, false) /
-                b:list_loose(true, true)
-              ) { a }
-        rule list_tight(ol: bool, loose: bool) -> Vec<Node>
-            = a:(list_item_tight())+
-              b:blank_lines_eof()? !bullet() !enumerator() {
-                let (start, _) = a.first().unwrap().span;
-                let (_, end) = a.last().unwrap().span;
-                let kind = match ol {
-                    false => Kind::UnorderedList(loose),
-                    true => Kind::OrderedList(loose),
-                };
-                let n = Node::new_block(kind, start, end, a);
-                match b.flatten() {
-                    Some(b) => vec![n, b],
-                    None => vec![n],
-                }
+
+        // Highlight, superscript and subscript
+        //
+        // Superscripts and subscripts follow Pandoc and may not contain spaces.
+        rule highlight() -> Node
+            = enabled((options.highlight))
+              a:$([Token::Equals((s, e)) if (e - s) == 2])
+              b:delimited(<equals_of(2)>) {
+                let (children, (_, end)) = b;
+                Node::new_block(Kind::Highlight, a[0].span().0, end, children)
               }
-        rule list_loose(ol: bool, loose: bool) -> Vec<Node>
-            = a:list_item()+
-             {
-                let (start, _) = a.first().unwrap().span;
-                let (_, end) = a.last().unwrap().span;
-                let kind = match ol {
-                    false => Kind::UnorderedList(loose),
-                    true => Kind::OrderedList(loose),
-                };
-                vec![Node::new_block(kind, start, end, a)]
+        rule equals_of(n: usize) -> Span
+            = a:$([Token::Equals((s, e)) if (e - s) == n]) { a[0].span() }
+        rule superscript() -> Node
+            = enabled((options.superscript))
+              a:$([Token::Caret((s, e)) if (e - s) == 1])
+              b:unspaced(<[Token::Caret(..)]>)
+              c:$([Token::Caret((s, e)) if (e - s) == 1]) {
+                Node::new_block(Kind::Superscript, a[0].span().0, c[0].span().1, b)
               }
-        rule list_item() -> Node
-            = width:(bullet() / enumerator())
-              a:list_block()
-              b:(list_continuation_block(width)*)
-              c:blank_lines_eof()? {
-                let s = [a, b.into_iter().flatten().collect()].concat();
-                let (start, _) = s.first().unwrap().span();
-                let (_, end) = s.last().unwrap().span();
-                let mut sub = md_parser::doc(&s).unwrap();
-                let (children, end) = match c.flatten() {
-                    Some(c) => {
-                        let end = c.span.1;
-                        sub.children.push(c);
-                        (sub.children, end)
-                    },
-                    None => (sub.children, end)
-                };
-                Node::new_block(Kind::ListItem, start, end, children)
+        rule subscript() -> Node
+            = enabled((options.subscript))
+              a:tildes_of(1)
+              b:unspaced(<[Token::Tilde(..)]>)
+              c:tildes_of(1) {
+                Node::new_block(Kind::Subscript, a.0, c.1, b)
               }
-        rule list_item_tight() -> Node
-            = width:(bullet() / enumerator())
-              a:list_block()
-              b:(!blank_line() c:list_continuation_block(width) { c })*
-              !list_continuation_block(width) {
-                let s = [a, b.into_iter().flatten().collect()].concat();
-                let (start, _) = s.first().unwrap().span();
-                let (_, end) = s.last().unwrap().span();
-                let sub = md_parser::doc(&s).unwrap();
-                Node::new_block(Kind::ListItem, start, end, sub.children)
+        rule unspaced(close: rule<()>) -> Vec<Node>
+            = a:$((![Token::Whitespace(..) | Token::Newline(..)] !close() [_])+) {
+                md_parser::inline_content(a, source, options).unwrap()
               }
-        rule list_block() -> Vec<Token>
-            = a:line() b:list_block_line()* {
-                [a, b.into_iter().flatten().collect()].concat()
-            }
-        rule list_continuation_indent(width: usize) -> Option<Token>
-            = a:$([Token::Whitespace((start, end)) if {(end - start) >= width} ]) {
-                let (start, _) = a.first().unwrap().span();
-                let (_, end) = a.last().unwrap().span();
-                if (start + width == end) {
-                    None
-                } else {
-                    // Chop off the indentation matching the current block
-                    Some(Token::Whitespace((start + width, end)))
-                }
-            }
-        rule list_continuation_block(width: usize) -> Vec<Token>
-            = blank_line()*
-              a:list_continuation_indent(width) b:line() c:list_block_line()* {
-                match a {
-                    Some(a) => [vec![a], b, c.into_iter().flatten().collect()].concat(),
-                    None => [b, c.into_iter().flatten().collect()].concat(),
-                }
-            }
-        rule list_block_line() -> Vec<Token>
-            = !blank_line() !(sp() (bullet() / enumerator()))
-              // !horizonatal_rule()
-              a:line()
-              { a }
-
-        // Paragraph
-        rule paragraph() -> Vec<Node>
-            = a:inlines() b:blank_lines_eof() {
-                let (start, _) = a.first().unwrap().span;
-                let (_, end) = a.last().unwrap().span;
-                let p = Node::new_block(Kind::Paragraph, start, end, a);
-                match b {
-                    Some(b) => vec![p, b],
-                    None => vec![p],
-                }
-            }
 
-        // Empty block
-        rule empty() -> Vec<Node>
-            = a:blank_lines() eof() { vec![a] }
+        // Math
+        //
+        // Follows the Pandoc rules: the closing dollar must not be followed by
+        // a digit, so amounts like "$5 and $10" stay plain text.
+        rule dollar() -> Span
+            = a:$([Token::Dollar((s, e)) if (e - s) == 1]) { a[0].span() }
+        rule math_close() -> Span
+            = a:dollar() !digit() { a }
+        rule math_inline() -> Node
+            = enabled((options.math)) a:dollar() delimited_body(<math_close()>) b:math_close() {
+                Node::new(Kind::MathInline, a.0, b.1)
+              }
 
-        // Inlines
-        rule inlines() -> Vec<Node>
-            = v:(!end_line() b:inline()+ { b })+
-            end_line()?
-            eof()?
-            { v.into_iter().flatten().collect() }
-        rule inline() -> Node
-            = text()
+        // Wiki links
+        rule wiki_link() -> Node
+            = a:$([Token::Bang(..)])?
+              b:$([Token::LeftBracket((s, e)) if (e - s) == 2])
+              c:$((![Token::LeftBracket(..) | Token::RightBracket(..) | Token::Newline(..)] [_])+)
+              d:$([Token::RightBracket((s, e)) if (e - s) == 2])
+              e:attributes()? {?
+                let (start, _) = a.unwrap_or(b)[0].span();
+                let (_, end) = d[0].span();
+                let (_, end) = e.as_ref().map(|e| e.span).unwrap_or((start, end));
+                let (inner, _) = c[0].span();
+                let (_, outer) = c[c.len() - 1].span();
+                wiki_link(source, a.is_some(), (start, end), (inner, outer)).map(|mut n| {
+                    n.attributes = e;
+                    n
+                })
+              }
 
-        rule text() -> Node
-            = a:(
-                non_continuing_text() /
-                continuation()
-            )+ {
-              let (start, _) = a.first().unwrap().span;
-              let (_, end) = a.last().unwrap().span;
-              Node::new(Kind::Plaintext, start, end)
-            }
-        rule non_continuing_text() -> Node
-            = a:(
-                plaintext() /
-                whitespace()
-            )+ {
-              let (start, _) = a.first().unwrap().span;
-              let (_, end) = a.last().unwrap().span;
-              Node::new(Kind::Plaintext, start, end)
-            }
+        // Attributes
+        rule attributes() -> Attributes
+            = a:$([Token::LeftBrace(..)])
+              b:$((![Token::LeftBrace(..) | Token::RightBrace(..) | Token::Newline(..)] [_])*)
+              c:$([Token::RightBrace(..)]) {?
+                let (_, start) = a[0].span();
+                let (end, _) = c[0].span();
+                attributes::parse(source, start, end).ok_or("attributes")
+              }
 
-        rule plaintext() -> Node
-            = a:$[
-                Token::Plaintext(..) |
-                Token::RightCaret(..) |
-                Token::Hash(..) |
-                Token::Dash(..) |
-                Token::Asterisk(..) |
-                Token::Plus(..) |
-                Token::NumDot(..) |
-                Token::NumParen(..)
-            ] {
-                let (start, end) = a[0].span();
-                Node::new(Kind::Plaintext, start, end)
-             } // x
-        rule whitespace() -> Node
-            = a:$([Token::Whitespace(..)]) {
-                let (start, _) = a.first().unwrap().span();
-                let (_, end) = a.last().unwrap().span();
-                Node::new(Kind::Whitespace, start, end)
-            }
-        rule continuation() -> Node
-            = a:end_line() &inline() {
-                Node::new(Kind::Whitespace, a.0, a.1)
-            }
+        // CriticMarkup
+        //
+        // Like inline HTML the closing marker is found in the source. The
+        // tokens in between are clipped to the content and parsed again.
+        rule critic() -> Node
+            = enabled((options.critic_markup))
+              a:$([Token::LeftBrace(..)])
+              b:critic_scan((a[0].span().0))
+              c:$(through((b.0))) {
+                critic(source, options, a[0].span().0, b, c)
+              }
+        rule critic_scan(start: usize) -> (usize, Critic)
+            = {? critic::scan(source, start).ok_or("critic markup") }
 
-        rule blank_lines_eof() -> Option<Node>
-            = (a:blank_lines() { Some(a) }) / (eof() { None })
-        rule blank_lines() -> Node
-            = a:blank_line()+ {
-                let (start, _) = a.first().unwrap().span;
-                let (_, end) = a.last().unwrap().span;
-                Node::new_block(Kind::Empty, start, end, a)
-            }
-        rule blank_line() -> Node
-            = a:sp() b:newline() {
-                let (start, _) = a.unwrap_or(b);
-                let (_, end) = b;
-                Node::new(Kind::EmptyLine, start, end)
-            }
+        // Inline HTML
+        rule inline_html() -> Node
+            = a:$([Token::LeftAngle(..)]) b:html_scan((a[0].span().0)) through((b.0)) {
+                let (start, _) = a[0].span();
+                let (end, html) = b;
+                Node::new(Kind::InlineHtml(html), start, end)
+              }
+        rule html_scan(start: usize) -> (usize, Html)
+            = {? html::scan(source, start).ok_or("inline html") }
+        rule through(end: usize)
+            = [t if t.span().1 < end]* [t if t.span().1 == end]
 
-        rule end_line() -> Span
-            = terminal_end_line() / normal_end_line()
-        rule normal_end_line() -> Span
-            = a:sp()
-              b:newline()
-              // The next line must not start with a block opener
-              !blank_line()
-              !block_quote_start()
-              !atx_start()
-              !bullet()
-              !enumerator() {
-                a.map(|span| {
-                    let (s, _) = span;
-                    let (_, e) = b;
-                    (s, e)
-                })
-                .unwrap_or(b)
-            }
-        rule terminal_end_line() -> Span
-            = a:sp() b:newline() eof() {
-                a.map(|span| {
-                    let (s, _) = span;
-                    let (_, e) = b;
-                    (s, e)
-                })
-                .unwrap_or(b)
-            }
+        rule digit()
+            = [
+                Token::Plaintext((s, _)) |
+                Token::NumDot((s, _)) |
+                Token::NumParen((s, _))
+                if source[s..].starts_with(|c: char| c.is_ascii_digit())
+            ]
 
-        rule newline() -> Span
-            = a:$([Token::Newline(..)]) { a[0].span() }
         rule eof()
             = ![_]
         rule sp() -> Option<Span>
@@ -388,50 +341,300 @@ peg::parser! {
                 let (_, e) = a.last().unwrap().span();
                 (s, e)
             }
-        rule non_indent_space() -> Option<Span>
-            = a:$([Token::Whitespace((start, end)) if (end - start) < 4])? {
-                a.and_then(|a| {
-                    if a.is_empty() {
-                        None
-                    } else {
-                        let (s, _) = a.first().unwrap().span();
-                        let (_, e) = a.last().unwrap().span();
-                        Some((s, e))
-                    }
-                })
+    }
+}
+
+/// Splits the inside of `[[target#anchor|label]]` into its parts.
+fn wiki_link(source: &str, embed: bool, span: Span, inner: Span) -> Result<Node, &'static str> {
+    let (start, end) = inner;
+    let text = &source[start..end];
+    let (link, label) = match text.find('|') {
+        Some(i) => ((start, start + i), Some((start + i + 1, end))),
+        None => ((start, end), None),
+    };
+    let (target, anchor) = match source[link.0..link.1].find('#') {
+        Some(i) => ((link.0, link.0 + i), Some((link.0 + i + 1, link.1))),
+        None => (link, None),
+    };
+    let blank = |(s, e): Span| source[s..e].trim().is_empty();
+    if blank(target) && anchor.is_none_or(blank) {
+        return Err("wiki link target");
+    }
+    let kind = Kind::WikiLink {
+        embed,
+        target,
+        anchor,
+        label,
+    };
+    Ok(Node::new(kind, span.0, span.1))
+}
+
+/// Builds the node for CriticMarkup between `start` and `end`. `tokens`
+/// follow the opening brace.
+fn critic(
+    source: &str,
+    options: &Options,
+    start: usize,
+    (end, critic): (usize, Critic),
+    tokens: &[Token],
+) -> Node {
+    let content = |(s, e): Span| {
+        let tokens = tokens
+            .iter()
+            .filter(|t| t.span().1 > s && t.span().0 < e)
+            .map(|t| t.clip(s, e))
+            .collect::<Vec<_>>();
+        md_parser::inline_content(&tokens, source, options).unwrap()
+    };
+    let inner = (start + 3, end - 3);
+    match critic {
+        Critic::Addition => Node::new_block(Kind::CriticAddition, start, end, content(inner)),
+        Critic::Deletion => Node::new_block(Kind::CriticDeletion, start, end, content(inner)),
+        Critic::Highlight => Node::new_block(Kind::CriticHighlight, start, end, content(inner)),
+        Critic::Comment => Node::new(Kind::CriticComment, start, end),
+        Critic::Substitution { arrow } => {
+            // The old and new text become a deletion and an addition without
+            // delimiters of their own
+            let (old, new) = ((start + 3, arrow), (arrow + 2, end - 3));
+            let children = vec![
+                Node::new_block(Kind::CriticDeletion, old.0, old.1, content(old)),
+                Node::new_block(Kind::CriticAddition, new.0, new.1, content(new)),
+            ];
+            Node::new_block(Kind::CriticSubstitution, start, end, children)
+        }
+    }
+}
+
+/// Splits `@mentions`, `#hashtags`, `:emoji:` shortcodes and smart
+/// punctuation out of plaintext nodes. Other inline nodes such as math and
+/// links are left alone.
+fn split_plaintext(source: &str, options: &Options, nodes: Vec<Node>) -> Vec<Node> {
+    let mut result = vec![];
+    for node in nodes {
+        if node.kind != Kind::Plaintext {
+            result.push(node);
+            continue;
+        }
+        let (start, end) = node.span;
+        let mut text = start;
+        let mut p = start;
+        while let Some(c) = source[p..end].chars().next() {
+            let before = source[..p].chars().next_back();
+            let opening = before.is_none_or(|c| c.is_whitespace() || "([{<\"'".contains(c));
+            if options.smart_punctuation && opening {
+                // Bare URLs keep their punctuation
+                if let Some(n) = scan_url(&source[p..end]) {
+                    p += n;
+                    continue;
+                }
             }
-        rule indent() -> Span
-            = a:$([Token::Whitespace((start, end)) if (end - start) == 4]) {
-                let (s, _) = a.first().unwrap().span();
-                let (_, e) = a.last().unwrap().span();
-                (s, e)
+            let found = match c {
+                '"' | '\'' | '-' | '.' if options.smart_punctuation => {
+                    scan_smart(source, p, end).map(|(n, s)| (Kind::SmartPunctuation(s), n))
+                }
+                _ if source[..p].ends_with(is_word_char) => None,
+                '@' if options.mentions => {
+                    scan_mention(&source[p + 1..end]).map(|n| (Kind::Mention, n))
+                }
+                '#' if options.hashtags => {
+                    scan_hashtag(&source[p + 1..end]).map(|n| (Kind::Hashtag, n))
+                }
+                ':' => scan_emoji(&source[p + 1..end]).map(|(n, e)| (Kind::Emoji(e), n)),
+                _ => None,
+            };
+            match found {
+                Some((kind, n)) => {
+                    if text < p {
+                        result.push(Node::new(Kind::Plaintext, text, p));
+                    }
+                    result.push(Node::new(kind, p, p + 1 + n));
+                    p += 1 + n;
+                    text = p;
+                }
+                None => p += c.len_utf8(),
             }
+        }
+        if text < end {
+            result.push(Node::new(Kind::Plaintext, text, end));
+        }
+    }
+    result
+}
 
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
 
-        rule line() -> Vec<Token>
-            = a:(
-                b:$((![Token::Newline(..)] [_])*) c:$([Token::Newline(..)]) {
-                    Vec::from([b, c].concat())
-                } /
-                b:$([_]+) eof() { Vec::from(b) }
-              )
-              { a.into() }
+/// Returns the length of the username at the start of `s`.
+fn scan_mention(s: &str) -> Option<usize> {
+    if !s.starts_with(is_word_char) {
+        return None;
     }
+    let len = s
+        .find(|c: char| !is_word_char(c) && c != '.' && c != '-')
+        .unwrap_or(s.len());
+    // Punctuation ending a sentence is not part of the name
+    Some(s[..len].trim_end_matches(['.', '-']).len())
 }
 
-pub fn parse(source: &str) -> Node {
+/// Returns the length of the tag at the start of `s`. Nested tags separate
+/// their segments with a single slash.
+fn scan_hashtag(s: &str) -> Option<usize> {
+    let mut len = 0;
+    for segment in s.split('/') {
+        let n = segment
+            .find(|c: char| !is_word_char(c) && c != '-')
+            .unwrap_or(segment.len());
+        if n == 0 {
+            break;
+        }
+        len += if len == 0 { n } else { 1 + n };
+        if n < segment.len() {
+            break;
+        }
+    }
+    // Bare numbers such as "#1" are references, not tags
+    match &s[..len] {
+        t if t.chars().all(|c| c.is_ascii_digit() || c == '/') => None,
+        _ => Some(len),
+    }
+}
+
+/// Returns the length of `shortcode:` at the start of `s` and its emoji.
+fn scan_emoji(s: &str) -> Option<(usize, &'static str)> {
+    let len = s.find(|c: char| !(c.is_ascii_alphanumeric() || "_+-".contains(c)))?;
+    match s[len..].starts_with(':') {
+        true => emoji::lookup(&s[..len]).map(|e| (len + 1, e)),
+        false => None,
+    }
+}
+
+/// Returns the length of the URL at the start of `s`. Punctuation closing
+/// the sentence or a bracket around the URL is not part of it.
+fn scan_url(s: &str) -> Option<usize> {
+    let len = s.find(char::is_whitespace).unwrap_or(s.len());
+    let mut url = match &s[..len] {
+        url if !url.starts_with(|c: char| c.is_ascii_alphanumeric()) => return None,
+        url if url.contains("://") || url.starts_with("www.") => url,
+        _ => return None,
+    };
+    loop {
+        url = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'']);
+        match url.strip_suffix(')') {
+            Some(u) if url.matches('(').count() < url.matches(')').count() => url = u,
+            _ => return Some(url.len()),
+        }
+    }
+}
+
+/// Returns the typographic replacement for the punctuation at `p`, and how
+/// many bytes after the first it covers. Dash runs other than two or three
+/// long are left alone.
+fn scan_smart(source: &str, p: usize, end: usize) -> Option<(usize, &'static str)> {
+    let s = &source[p..end];
+    let run = |c: char| s.find(|x| x != c).unwrap_or(s.len());
+    match s.chars().next()? {
+        '-' if source[..p].ends_with('-') => None,
+        '-' => match run('-') {
+            2 => Some((1, "\u{2013}")),
+            3 => Some((2, "\u{2014}")),
+            _ => None,
+        },
+        '.' if s.starts_with("...") => Some((2, "\u{2026}")),
+        quote @ ('"' | '\'') => {
+            // Quotes directly after another quote open or close along with it
+            let before = source[..p]
+                .trim_end_matches(['"', '\''])
+                .chars()
+                .next_back();
+            let after = source[p + 1..].chars().next();
+            let opening =
+                before.is_none_or(|c| c.is_whitespace() || "([{\u{2013}\u{2014}-".contains(c));
+            let smart = match (quote, opening) {
+                // Abbreviated years such as '90s
+                ('\'', true) if after.is_some_and(|c| c.is_ascii_digit()) => "\u{2019}",
+                ('"', true) => "\u{201c}",
+                ('"', false) => "\u{201d}",
+                (_, true) => "\u{2018}",
+                (_, false) => "\u{2019}",
+            };
+            Some((0, smart))
+        }
+        _ => None,
+    }
+}
+
+pub fn parse(source: &str, options: &Options) -> Node {
     let tokenizer = Tokenizer::new(0, source);
     let tokens = tokenizer.collect::<Vec<_>>();
-    md_parser::doc(&tokens).unwrap()
+    block::parse(&tokens, source, options)
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     extern crate test;
 
     use super::*;
     use test::Bencher;
 
+    fn parse(source: &str) -> Node {
+        super::parse(source, &Options::default())
+    }
+
+    fn parse_math(source: &str) -> Node {
+        super::parse(
+            source,
+            &Options {
+                math: true,
+                ..Options::default()
+            },
+        )
+    }
+
+    fn parse_scripts(source: &str) -> Node {
+        super::parse(
+            source,
+            &Options {
+                highlight: true,
+                superscript: true,
+                subscript: true,
+                ..Options::default()
+            },
+        )
+    }
+
+    fn parse_critic(source: &str) -> Node {
+        super::parse(
+            source,
+            &Options {
+                critic_markup: true,
+                ..Options::default()
+            },
+        )
+    }
+
+    fn parse_smart(source: &str) -> Node {
+        super::parse(
+            source,
+            &Options {
+                smart_punctuation: true,
+                math: true,
+                ..Options::default()
+            },
+        )
+    }
+
+    fn parse_tags(source: &str) -> Node {
+        super::parse(
+            source,
+            &Options {
+                mentions: true,
+                hashtags: true,
+                ..Options::default()
+            },
+        )
+    }
+
     macro_rules! doc {
         ($start:literal $end:literal $($child:expr )*) => {
            Node::new_block(Kind::Document, $start, $end, vec![$($child),*])
@@ -440,13 +643,13 @@ mod test {
 
     macro_rules! empty {
         ($start:literal $end:literal $($child:expr )*) => (
-           Node::new_block(Kind::Empty, $start, $end, vec![$($child),*]);
+           Node::new_block(Kind::Empty, $start, $end, vec![$($child),*])
         );
     }
 
     macro_rules! empty_line {
         ($start:literal $end:literal $($child:expr )*) => (
-           Node::new_block(Kind::EmptyLine, $start, $end, vec![$($child),*]);
+           Node::new_block(Kind::EmptyLine, $start, $end, vec![$($child),*])
         );
     }
 
@@ -456,6 +659,7 @@ mod test {
         };
     }
 
+    #[allow(unused_macros)]
     macro_rules! ws {
         ($start:literal $end:literal) => {
             Node::new(Kind::Whitespace, $start, $end)
@@ -477,6 +681,93 @@ mod test {
         };
     }
 
+    macro_rules! math {
+        ($start:literal $end:literal) => {
+            Node::new(Kind::MathInline, $start, $end)
+        };
+    }
+
+    macro_rules! wiki {
+        ($start:literal $end:literal $embed:literal $target:expr, $anchor:expr, $label:expr) => {
+            Node::new(
+                Kind::WikiLink {
+                    embed: $embed,
+                    target: $target,
+                    anchor: $anchor,
+                    label: $label,
+                },
+                $start,
+                $end,
+            )
+        };
+    }
+
+    macro_rules! mention {
+        ($start:literal $end:literal) => {
+            Node::new(Kind::Mention, $start, $end)
+        };
+    }
+
+    macro_rules! tag {
+        ($start:literal $end:literal) => {
+            Node::new(Kind::Hashtag, $start, $end)
+        };
+    }
+
+    macro_rules! emoji {
+        ($start:literal $end:literal $emoji:literal) => {
+            Node::new(Kind::Emoji($emoji), $start, $end)
+        };
+    }
+
+    macro_rules! mark {
+        ($start:literal $end:literal $($child:expr )*) => {
+            Node::new_block(Kind::Highlight, $start, $end, vec![$($child),*])
+        };
+    }
+
+    macro_rules! sup {
+        ($start:literal $end:literal $($child:expr )*) => {
+            Node::new_block(Kind::Superscript, $start, $end, vec![$($child),*])
+        };
+    }
+
+    macro_rules! sub {
+        ($start:literal $end:literal $($child:expr )*) => {
+            Node::new_block(Kind::Subscript, $start, $end, vec![$($child),*])
+        };
+    }
+
+    macro_rules! html {
+        ($start:literal $end:literal $html:expr) => {
+            Node::new(Kind::InlineHtml($html), $start, $end)
+        };
+    }
+
+    macro_rules! critic {
+        ($kind:ident $start:literal $end:literal $($child:expr )*) => {
+            Node::new_block(Kind::$kind, $start, $end, vec![$($child),*])
+        };
+    }
+
+    macro_rules! smart {
+        ($start:literal $end:literal $s:literal) => {
+            Node::new(Kind::SmartPunctuation($s), $start, $end)
+        };
+    }
+
+    fn with_attributes(mut node: Node, attributes: Attributes) -> Node {
+        node.attributes = Some(attributes);
+        node
+    }
+
+    macro_rules! del {
+        ($start:literal $end:literal $($child:expr )*) => {
+            Node::new_block(Kind::Strikethrough, $start, $end, vec![$($child),*])
+        };
+    }
+
+    #[allow(unused_macros)]
     macro_rules! bq {
         ($start:literal $end:literal $($child:expr )*) => {
             Node::new_block(Kind::BlockQuote, $start, $end, vec![$($child),*])
@@ -493,6 +784,7 @@ mod test {
         };
     }
 
+    #[allow(unused_macros)]
     macro_rules! ol {
         ($start:literal $end:literal $($child:expr )*) => {
             Node::new_block(Kind::OrderedList(false), $start, $end, vec![$($child),*])
@@ -577,15 +869,23 @@ mod test {
                 p!(2 4 plain!(2 4))
             )
         );
+    }
+
+    #[test]
+    fn test_heading_after_list() {
         assert_eq!(
             parse("* \n# Heading\n\n"),
             doc!(
                 0 14
-                ul!(0 3 li!(2 3))
-                h!(# 5 12)
+                ul!(2 3 li!(2 3 empty!(2 3 empty_line!(2 3))))
+                h!(# 5 12 plain!(5 12))
                 empty!(12 14 empty_line!(12 13) empty_line!(13 14))
             )
-        )
+        );
+        assert_eq!(
+            parse("* A\n# B"),
+            doc!(0 7 ul!(2 4 li!(2 4 p!(2 3 plain!(2 3)))) h!(# 6 7 plain!(6 7)))
+        );
     }
 
     #[test]
@@ -680,8 +980,427 @@ mod test {
         dbg!(&result);
     }
 
+    #[test]
+    fn test_strikethrough() {
+        assert_eq!(parse("~~Done~~"), doc!(0 8 p!(0 8 del!(0 8 plain!(2 6)))));
+        assert_eq!(
+            parse("A ~B~ C"),
+            doc!(0 7 p!(0 7 plain!(0 2) del!(2 5 plain!(3 4)) plain!(5 7)))
+        );
+        assert_eq!(parse("~~A\nB~~"), doc!(0 7 p!(0 7 del!(0 7 plain!(2 5)))));
+        assert_eq!(
+            parse("~~A ~~ B~~"),
+            doc!(0 10 p!(0 10 del!(0 10 plain!(2 8))))
+        );
+        assert_eq!(
+            parse("~~~A ~B~"),
+            doc!(0 8 p!(0 8 plain!(0 5) del!(5 8 plain!(6 7))))
+        );
+        assert_eq!(parse("# ~~A~~"), doc!(0 7 h!(# 2 7 del!(2 7 plain!(4 5)))));
+    }
+
+    #[test]
+    fn test_strikethrough_unmatched() {
+        // Differing run lengths never match
+        assert_eq!(parse("~~A~"), doc!(0 4 p!(0 4 plain!(0 4))));
+        assert_eq!(parse("~A~~"), doc!(0 4 p!(0 4 plain!(0 4))));
+        // Runs must hug their content
+        assert_eq!(parse("~~ A~~"), doc!(0 6 p!(0 6 plain!(0 6))));
+        assert_eq!(parse("~~A ~~"), doc!(0 6 p!(0 6 plain!(0 6))));
+        assert_eq!(parse("~~~A~~~"), doc!(0 7 p!(0 7 plain!(0 7))));
+    }
+
+    #[test]
+    fn test_math_inline() {
+        assert_eq!(parse_math("$x^2$"), doc!(0 5 p!(0 5 math!(0 5))));
+        assert_eq!(
+            parse_math("Let $a ~b~ c$ be"),
+            doc!(0 16 p!(0 16 plain!(0 4) math!(4 13) plain!(13 16)))
+        );
+        assert_eq!(
+            parse_math("$a$ and $b\nc$"),
+            doc!(0 13 p!(0 13 math!(0 3) plain!(3 8) math!(8 13)))
+        );
+        // Closing dollar may not be preceded by a space or followed by a digit
+        assert_eq!(parse_math("$a $b$"), doc!(0 6 p!(0 6 math!(0 6))));
+        assert_eq!(parse_math("$a$1 b$"), doc!(0 7 p!(0 7 math!(0 7))));
+    }
+
+    #[test]
+    fn test_math_inline_plaintext() {
+        assert_eq!(parse_math("$5 and $10"), doc!(0 10 p!(0 10 plain!(0 10))));
+        assert_eq!(parse_math("$ x$"), doc!(0 4 p!(0 4 plain!(0 4))));
+        assert_eq!(parse_math("$$x$$"), doc!(0 5 p!(0 5 plain!(0 5))));
+        // Disabled unless the extension is turned on
+        assert_eq!(parse("$x$"), doc!(0 3 p!(0 3 plain!(0 3))));
+    }
+
+    #[test]
+    fn test_wiki_link() {
+        assert_eq!(
+            parse("[[Page]]"),
+            doc!(0 8 p!(0 8 wiki!(0 8 false (2, 6), None, None)))
+        );
+        assert_eq!(
+            parse("See [[Page#Heading|alias]]."),
+            doc!(0 27 p!(0 27
+                plain!(0 4)
+                wiki!(4 26 false (6, 10), Some((11, 18)), Some((19, 24)))
+                plain!(26 27)
+            ))
+        );
+        assert_eq!(
+            parse("[[#Heading]]"),
+            doc!(0 12 p!(0 12 wiki!(0 12 false (2, 2), Some((3, 10)), None)))
+        );
+        assert_eq!(
+            parse("![[diagram.png]]"),
+            doc!(0 16 p!(0 16 wiki!(0 16 true (3, 14), None, None)))
+        );
+        assert_eq!(
+            parse("# [[A]] B"),
+            doc!(0 9 h!(# 2 9 wiki!(2 7 false (4, 5), None, None) plain!(7 9)))
+        );
+    }
+
+    #[test]
+    fn test_wiki_link_plaintext() {
+        assert_eq!(parse("[[]]"), doc!(0 4 p!(0 4 plain!(0 4))));
+        assert_eq!(parse("[[ | x]]"), doc!(0 8 p!(0 8 plain!(0 8))));
+        assert_eq!(parse("[Page]"), doc!(0 6 p!(0 6 plain!(0 6))));
+        assert_eq!(parse("[[A\nB]]"), doc!(0 7 p!(0 7 plain!(0 7))));
+        assert_eq!(parse("Hi! [[A"), doc!(0 7 p!(0 7 plain!(0 7))));
+    }
+
+    #[test]
+    fn test_mentions() {
+        assert_eq!(parse_tags("@bob"), doc!(0 4 p!(0 4 mention!(0 4))));
+        assert_eq!(
+            parse_tags("Thanks @jane.doe, and @x."),
+            doc!(0 25 p!(0 25
+                plain!(0 7)
+                mention!(7 16)
+                plain!(16 22)
+                mention!(22 24)
+                plain!(24 25)
+            ))
+        );
+        // Email addresses are not mentions
+        assert_eq!(parse_tags("mail a@b.com"), doc!(0 12 p!(0 12 plain!(0 12))));
+        assert_eq!(parse_tags("@ bob"), doc!(0 5 p!(0 5 plain!(0 5))));
+        assert_eq!(parse("@bob"), doc!(0 4 p!(0 4 plain!(0 4))));
+    }
+
+    #[test]
+    fn test_hashtags() {
+        assert_eq!(
+            parse_tags("A #tag B"),
+            doc!(0 8 p!(0 8 plain!(0 2) tag!(2 6) plain!(6 8)))
+        );
+        assert_eq!(
+            parse_tags("(#project/alpha/)"),
+            doc!(0 17 p!(0 17 plain!(0 1) tag!(1 15) plain!(15 17)))
+        );
+        assert_eq!(
+            parse_tags("# Plan #todo"),
+            doc!(0 12 h!(# 2 12 plain!(2 7) tag!(7 12)))
+        );
+        // Tags never start inside a word or consist of a bare number
+        assert_eq!(parse_tags("C#sharp"), doc!(0 7 p!(0 7 plain!(0 7))));
+        assert_eq!(parse_tags("Fixes #12"), doc!(0 9 p!(0 9 plain!(0 9))));
+        // Links keep their text
+        assert_eq!(
+            parse_tags("[[Page#tag]]"),
+            doc!(0 12 p!(0 12 wiki!(0 12 false (2, 6), Some((7, 10)), None)))
+        );
+    }
+
+    #[test]
+    fn test_emoji() {
+        assert_eq!(parse(":tada:"), doc!(0 6 p!(0 6 emoji!(0 6 "\u{1f389}"))));
+        assert_eq!(
+            parse("Ship it :+1::rocket:!"),
+            doc!(0 21 p!(0 21
+                plain!(0 8)
+                emoji!(8 12 "\u{1f44d}")
+                emoji!(12 20 "\u{1f680}")
+                plain!(20 21)
+            ))
+        );
+        assert_eq!(
+            parse("~~:x:~~"),
+            doc!(0 7 p!(0 7 del!(0 7 emoji!(2 5 "\u{274c}"))))
+        );
+        // Unknown codes and codes inside words stay plain text
+        assert_eq!(parse(":nope: a:x:"), doc!(0 11 p!(0 11 plain!(0 11))));
+        assert_eq!(parse("10:30 :"), doc!(0 7 p!(0 7 plain!(0 7))));
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            parse_scripts("==Key point=="),
+            doc!(0 13 p!(0 13 mark!(0 13 plain!(2 11))))
+        );
+        assert_eq!(
+            parse_scripts("a == b == c"),
+            doc!(0 11 p!(0 11 plain!(0 11)))
+        );
+        assert_eq!(parse("==A=="), doc!(0 5 p!(0 5 plain!(0 5))));
+    }
+
+    #[test]
+    fn test_superscript_subscript() {
+        assert_eq!(
+            parse_scripts("2^10^"),
+            doc!(0 5 p!(0 5 plain!(0 1) sup!(1 5 plain!(2 4))))
+        );
+        assert_eq!(
+            parse_scripts("H~2~O"),
+            doc!(0 5 p!(0 5 plain!(0 1) sub!(1 4 plain!(2 3)) plain!(4 5)))
+        );
+        assert_eq!(
+            parse_scripts("~~H~2~O~~"),
+            doc!(0 9 p!(0 9 del!(0 9 plain!(2 3) sub!(3 6 plain!(4 5)) plain!(6 7))))
+        );
+        // Spaces end a superscript or subscript
+        assert_eq!(parse_scripts("^a b^"), doc!(0 5 p!(0 5 plain!(0 5))));
+        assert_eq!(parse_scripts("~a b~"), doc!(0 5 p!(0 5 plain!(0 5))));
+        // Without subscripts a single tilde is still a strikethrough
+        assert_eq!(parse("~a b~"), doc!(0 5 p!(0 5 del!(0 5 plain!(1 4)))));
+        assert_eq!(parse("2^10^"), doc!(0 5 p!(0 5 plain!(0 5))));
+    }
+
+    #[test]
+    fn test_inline_html() {
+        assert_eq!(
+            parse("a <b class=\"x\">bold</b>"),
+            doc!(0 23 p!(0 23
+                plain!(0 2)
+                html!(2 15 Html::Open { name: (3, 4), self_closing: false })
+                plain!(15 19)
+                html!(19 23 Html::Close { name: (21, 22) })
+            ))
+        );
+        assert_eq!(
+            parse("<!-- a\nb -->"),
+            doc!(0 12 p!(0 12 html!(0 12 Html::Comment)))
+        );
+        assert_eq!(
+            parse("x<br/>y"),
+            doc!(0 7 p!(0 7
+                plain!(0 1)
+                html!(1 6 Html::Open { name: (2, 4), self_closing: true })
+                plain!(6 7)
+            ))
+        );
+        assert_eq!(
+            parse("<![CDATA[ ~~x~~ ]]>"),
+            doc!(0 19 p!(0 19 html!(0 19 Html::Cdata)))
+        );
+        assert_eq!(parse("a < b > c"), doc!(0 9 p!(0 9 plain!(0 9))));
+        assert_eq!(
+            parse("<!-- a\n\nb -->"),
+            doc!(0 13
+                p!(0 6 plain!(0 6))
+                empty!(6 8 empty_line!(6 7) empty_line!(7 8))
+                p!(8 13 plain!(8 13))
+            )
+        );
+    }
+
+    #[test]
+    fn test_critic_markup() {
+        assert_eq!(
+            parse_critic("a {++b\nc++}{-- ~~d~~ --}"),
+            doc!(0 24 p!(0 24
+                plain!(0 2)
+                critic!(CriticAddition 2 11 plain!(5 8))
+                critic!(CriticDeletion 11 24
+                    plain!(14 15)
+                    del!(15 20 plain!(17 18))
+                    plain!(20 21)
+                )
+            ))
+        );
+        assert_eq!(
+            parse_critic("{~~a~>b~~}{==c==}{>>d<<}"),
+            doc!(0 24 p!(0 24
+                critic!(CriticSubstitution 0 10
+                    critic!(CriticDeletion 3 4 plain!(3 4))
+                    critic!(CriticAddition 6 7 plain!(6 7))
+                )
+                critic!(CriticHighlight 10 17 plain!(13 14))
+                critic!(CriticComment 17 24)
+            ))
+        );
+        assert_eq!(parse("{++a++}"), doc!(0 7 p!(0 7 plain!(0 7))));
+        assert_eq!(
+            parse_critic("{++a\n\nb++}"),
+            doc!(0 10
+                p!(0 4 plain!(0 4))
+                empty!(4 6 empty_line!(4 5) empty_line!(5 6))
+                p!(6 10 plain!(6 10))
+            )
+        );
+    }
+
+    #[test]
+    fn test_smart_punctuation() {
+        assert_eq!(
+            parse_smart("\"It's '90s--ish...\""),
+            doc!(0 19 p!(0 19
+                smart!(0 1 "\u{201c}")
+                plain!(1 3)
+                smart!(3 4 "\u{2019}")
+                plain!(4 6)
+                smart!(6 7 "\u{2019}")
+                plain!(7 10)
+                smart!(10 12 "\u{2013}")
+                plain!(12 15)
+                smart!(15 18 "\u{2026}")
+                smart!(18 19 "\u{201d}")
+            ))
+        );
+        assert_eq!(
+            parse_smart("a --- 'b' ----"),
+            doc!(0 14 p!(0 14
+                plain!(0 2)
+                smart!(2 5 "\u{2014}")
+                plain!(5 6)
+                smart!(6 7 "\u{2018}")
+                plain!(7 8)
+                smart!(8 9 "\u{2019}")
+                plain!(9 14)
+            ))
+        );
+        // Math and URLs are left alone
+        assert_eq!(
+            parse_smart("$a''$ http://x.org/a--b"),
+            doc!(0 23 p!(0 23 math!(0 5) plain!(5 23)))
+        );
+        assert_eq!(
+            parse_smart("(http://a--b) \"http://x.org/a...b\""),
+            doc!(0 34 p!(0 34
+                plain!(0 14)
+                smart!(14 15 "\u{201c}")
+                plain!(15 33)
+                smart!(33 34 "\u{201d}")
+            ))
+        );
+        assert_eq!(parse("\"a\""), doc!(0 3 p!(0 3 plain!(0 3))));
+    }
+
+    #[test]
+    fn test_heading_attributes() {
+        assert_eq!(
+            parse("# Intro {#start .big}"),
+            doc!(0 21 with_attributes(
+                h!(# 2 21 plain!(2 7)),
+                Attributes {
+                    span: (8, 21),
+                    id: Some((10, 15)),
+                    classes: vec![(17, 20)],
+                    pairs: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("## A {x=1}\nB"),
+            doc!(0 12
+                with_attributes(
+                    h!(## 3 10 plain!(3 4)),
+                    Attributes {
+                        span: (5, 10),
+                        id: None,
+                        classes: vec![],
+                        pairs: vec![((6, 7), (8, 9))],
+                    }
+                )
+                empty!(10 11 empty_line!(10 11))
+                p!(11 12 plain!(11 12))
+            )
+        );
+        assert_eq!(
+            parse("# {.a} "),
+            doc!(0 6 with_attributes(
+                h!(# 2 6),
+                Attributes {
+                    span: (2, 6),
+                    id: None,
+                    classes: vec![(4, 5)],
+                    pairs: vec![],
+                }
+            ))
+        );
+        // Braces that are not at the end or not well formed stay in the text
+        assert_eq!(parse("# {#a} b"), doc!(0 8 h!(# 2 8 plain!(2 8))));
+        assert_eq!(parse("# Set {a, b}"), doc!(0 12 h!(# 2 12 plain!(2 12))));
+    }
+
+    #[test]
+    fn test_link_attributes() {
+        assert_eq!(
+            parse("![[cat.png]]{width=50%} [[A]] {.b}"),
+            doc!(0 34 p!(0 34
+                with_attributes(
+                    wiki!(0 23 true (3, 10), None, None),
+                    Attributes {
+                        span: (12, 23),
+                        id: None,
+                        classes: vec![],
+                        pairs: vec![((13, 18), (19, 22))],
+                    }
+                )
+                plain!(23 24)
+                wiki!(24 29 false (26, 27), None, None)
+                plain!(29 34)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        assert_eq!(
+            parse("# Grüße\nÜber [[Köln]]"),
+            doc!(0 25
+                h!(# 2 9 plain!(2 9))
+                empty!(9 10 empty_line!(9 10))
+                p!(10 25 plain!(10 16) wiki!(16 25 false (18, 23), None, None))
+            )
+        );
+    }
+
     #[bench]
     fn bench_simple_parse(b: &mut Bencher) {
         b.iter(|| parse("> Hello,\nWorld!\n\n"));
     }
+
+    /// Every syntax extension turned on.
+    pub(crate) fn options() -> Options {
+        Options {
+            math: true,
+            mentions: true,
+            hashtags: true,
+            highlight: true,
+            superscript: true,
+            subscript: true,
+            critic_markup: true,
+            smart_punctuation: true,
+        }
+    }
+
+    /// About a megabyte of notes written the way people write them, mostly
+    /// prose with the odd link, list and emphasis. tests/js.rs reads the
+    /// same file.
+    pub(crate) fn large() -> String {
+        let sample = include_str!("../../../tests/notes.md");
+        sample.repeat(1_000_000 / sample.len())
+    }
+
+    #[bench]
+    fn bench_large_parse(b: &mut Bencher) {
+        let source = large();
+        b.iter(|| parse(&source));
+    }
 }
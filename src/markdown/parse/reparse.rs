@@ -0,0 +1,234 @@
+use crate::markdown::parse::block;
+use crate::markdown::parse::token::Tokenizer;
+use crate::markdown::parse::{Html, Kind, Node, Options};
+
+/// Parses `source` again after `start..end` of the text `doc` was parsed
+/// from was replaced with `inserted` bytes, returning the same tree as
+/// `parse` would.
+///
+/// Top level blocks start parsing where the blank lines before them end, so
+/// only the blocks between two runs of blank lines around the edit are
+/// parsed again. The ones after are kept with their spans shifted. Lists
+/// are the only blocks that look past blank lines, so the range grows until
+/// none sits at either end of it.
+pub fn reparse(
+    source: &str,
+    doc: Node,
+    (start, end): (usize, usize),
+    inserted: usize,
+    options: &Options,
+) -> Node {
+    let mut children = doc.children;
+    let len = children.len();
+    let delta = inserted as isize - (end - start) as isize;
+    let is_blank = |n: &Node| n.kind == Kind::Empty;
+    let is_list = |n: &Node| matches!(n.kind, Kind::UnorderedList(..) | Kind::OrderedList(..));
+    let ends_in_list = |nodes: &[Node]| {
+        nodes
+            .iter()
+            .rev()
+            .find(|n| !is_blank(n))
+            .is_some_and(is_list)
+    };
+
+    // Start a block early, the end of the one before the edit may change
+    let first = children.iter().position(|n| n.span.1 >= start);
+    let mut s = first.unwrap_or(len).saturating_sub(1);
+    let mut k = (s + 1).min(len);
+    loop {
+        while s > 0 && (!is_blank(&children[s - 1]) || ends_in_list(&children[..s])) {
+            s -= 1;
+        }
+        while k < len && (!is_blank(&children[k - 1]) || children[k - 1].span.1 < end) {
+            k += 1;
+        }
+        let q = match s {
+            0 => 0,
+            s => children[s - 1].span.1,
+        };
+        let r = match k {
+            k if k == len => source.len(),
+            k => (children[k - 1].span.1 as isize + delta) as usize,
+        };
+        let tokens = Tokenizer::new(q, &source[..r]).collect::<Vec<_>>();
+        let slice = block::parse(&tokens, source, options).children;
+
+        // The slice must not start with blank lines the block before would
+        // have taken, nor end where a block could continue past them
+        let left = s == 0 || slice.first().is_some_and(|n| !is_blank(n));
+        let right = k == len || slice.last().is_some_and(is_blank) && !ends_in_list(&slice);
+        if left && right {
+            let mut suffix = children.split_off(k);
+            for n in &mut suffix {
+                shift(n, delta);
+            }
+            children.truncate(s);
+            children.extend(slice);
+            children.extend(suffix);
+            let end = children.last().map(|n| n.span.1).unwrap_or(0);
+            return Node::new_block(Kind::Document, 0, end, children);
+        }
+        if !left {
+            s -= 1;
+        }
+        if !right {
+            k += 1;
+        }
+    }
+}
+
+/// Moves every span in the node by `delta` bytes.
+fn shift(node: &mut Node, delta: isize) {
+    let by = |(s, e): (usize, usize)| {
+        let s = (s as isize + delta) as usize;
+        let e = (e as isize + delta) as usize;
+        (s, e)
+    };
+    node.span = by(node.span);
+    match &mut node.kind {
+        Kind::WikiLink {
+            target,
+            anchor,
+            label,
+            ..
+        } => {
+            *target = by(*target);
+            *anchor = anchor.map(by);
+            *label = label.map(by);
+        }
+        Kind::InlineHtml(Html::Open { name, .. }) | Kind::InlineHtml(Html::Close { name }) => {
+            *name = by(*name);
+        }
+        _ => {}
+    }
+    if let Some(a) = &mut node.attributes {
+        a.span = by(a.span);
+        a.id = a.id.map(by);
+        for class in &mut a.classes {
+            *class = by(*class);
+        }
+        for (k, v) in &mut a.pairs {
+            *k = by(*k);
+            *v = by(*v);
+        }
+    }
+    for child in &mut node.children {
+        shift(child, delta);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate test;
+
+    use super::*;
+    use crate::markdown::parse::parse;
+    use crate::markdown::parse::test::{large, options};
+    use proptest::prelude::*;
+    use test::Bencher;
+
+    /// Applies the edit and checks the result against a full parse.
+    fn assert_reparses(source: &str, start: usize, end: usize, replacement: &str) {
+        let options = options();
+        let doc = parse(source, &options);
+        let edited = [&source[..start], replacement, &source[end..]].concat();
+        let result = reparse(&edited, doc, (start, end), replacement.len(), &options);
+        assert_eq!(
+            result,
+            parse(&edited, &options),
+            "{:?} with {}..{} replaced by {:?}",
+            source,
+            start,
+            end,
+            replacement
+        );
+    }
+
+    #[test]
+    fn test_reparse() {
+        let source = "# Title {#t}\n\nSome [[Page|text]] <b>x</b>\n\n* a\n* b\n\n> quote\n\nEnd";
+        assert_reparses(source, 0, 0, "x");
+        assert_reparses(source, 14, 18, "More");
+        assert_reparses(source, 12, 14, "\n");
+        assert_reparses(source, 49, 50, "");
+        assert_reparses(source, 50, 50, "  c\n\n");
+        assert_reparses(source, source.len(), source.len(), "\n\n* list");
+        assert_reparses(source, 0, source.len(), "");
+        assert_reparses("", 0, 0, "a\n\n\n");
+        assert_reparses("\n\n", 1, 1, "a");
+    }
+
+    #[test]
+    fn test_shift() {
+        let source = "a\n\n# B {#b}\n\n[[C#d|e]] <i>";
+        let doc = parse(source, &options());
+        let edited = format!("xyz{}", source);
+        let result = reparse(&edited, doc, (0, 0), 3, &options());
+        assert_eq!(result, parse(&edited, &options()));
+    }
+
+    fn word() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec![
+            "a", "bb", "~~", "==", "^", "$", "[[", "]]", "|", "{", "}", "#", "@me", ":tada:",
+            "<b>", "</b>", "{++", "++}", "\"", "--", "...",
+        ])
+    }
+
+    fn line() -> impl Strategy<Value = String> {
+        let start = prop::sample::select(vec!["", "", "# ", "## ", "* ", "- ", "1. ", "> ", "  "]);
+        (start, prop::collection::vec(word(), 0..5))
+            .prop_map(|(start, w)| format!("{}{}", start, w.join(" ")))
+    }
+
+    /// Lines with blank ones in between, so there is something to skip.
+    fn document() -> impl Strategy<Value = String> {
+        let separator = prop::sample::select(vec!["\n", "\n", "\n\n", "\n\n\n"]);
+        prop::collection::vec((line(), separator), 0..12)
+            .prop_map(|l| l.into_iter().map(|(l, s)| l + s).collect())
+    }
+
+    fn edit() -> impl Strategy<Value = (String, usize, usize, String)> {
+        let replacement = prop_oneof![
+            Just(String::new()),
+            Just("\n".to_string()),
+            Just("\n\n".to_string()),
+            line(),
+            line().prop_map(|l| format!("\n{}\n", l)),
+        ];
+        (
+            document(),
+            any::<prop::sample::Index>(),
+            0..12usize,
+            replacement,
+        )
+            .prop_map(|(source, i, len, replacement)| {
+                // Sources are ASCII so every offset is a char boundary
+                let start = i.index(source.len() + 1);
+                let end = (start + len).min(source.len());
+                (source, start, end, replacement)
+            })
+    }
+
+    #[bench]
+    fn bench_reparse(b: &mut Bencher) {
+        let source = large();
+        let doc = parse(&source, &options());
+        let at = source.len() / 2;
+        let edited = [&source[..at], "x", &source[at..]].concat();
+        // The tree is consumed, so cloning it is part of what is measured
+        b.iter(|| reparse(&edited, doc.clone(), (at, at), 1, &options()));
+    }
+
+    #[bench]
+    fn bench_full_parse(b: &mut Bencher) {
+        let source = large();
+        b.iter(|| parse(&source, &options()));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_reparse((source, start, end, replacement) in edit()) {
+            assert_reparses(&source, start, end, &replacement);
+        }
+    }
+}
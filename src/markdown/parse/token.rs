@@ -1,5 +1,19 @@
-const WHITESPACE_CHARS: [&str; 2] = [" ", "\t"];
-const NUMBER_CHARS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+/// A table with the given bytes set, for checking a byte with one load.
+const fn table(bytes: &[u8]) -> [bool; 256] {
+    let mut table = [false; 256];
+    let mut i = 0;
+    while i < bytes.len() {
+        table[bytes[i] as usize] = true;
+        i += 1;
+    }
+    table
+}
+
+const WHITESPACE: [bool; 256] = table(b" \t");
+const DIGITS: [bool; 256] = table(b"0123456789");
+/// Bytes plaintext stops before. A `!` only does when a `[` follows it.
+/// All of them are ASCII, so tokens never split a UTF-8 character.
+const SPECIAL: [bool; 256] = table(b" \t\n~$[]=^<>{}!");
 
 pub type Span = (usize, usize);
 
@@ -12,6 +26,16 @@ pub enum Token {
     Plus(Span),
     NumDot(Span),
     NumParen(Span),
+    Tilde(Span),
+    Dollar(Span),
+    Bang(Span),
+    LeftBracket(Span),
+    RightBracket(Span),
+    Equals(Span),
+    Caret(Span),
+    LeftAngle(Span),
+    LeftBrace(Span),
+    RightBrace(Span),
     Plaintext(Span),
     Whitespace(Span),
     Newline(Span),
@@ -27,31 +51,95 @@ impl Token {
             Token::Plus(s) => *s,
             Token::NumDot(s) => *s,
             Token::NumParen(s) => *s,
+            Token::Tilde(s) => *s,
+            Token::Dollar(s) => *s,
+            Token::Bang(s) => *s,
+            Token::LeftBracket(s) => *s,
+            Token::RightBracket(s) => *s,
+            Token::Equals(s) => *s,
+            Token::Caret(s) => *s,
+            Token::LeftAngle(s) => *s,
+            Token::LeftBrace(s) => *s,
+            Token::RightBrace(s) => *s,
             Token::Plaintext(s) => *s,
             Token::Whitespace(s) => *s,
             Token::Newline(s) => *s,
         }
     }
-}
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-enum TokenizerState {
-    Unset,
-    Done,
-    Hash,
-    Plaintext,
-    Whitespace,
-    Number,
+    /// The same token cut down to the part inside `start..end`.
+    pub fn clip(&self, start: usize, end: usize) -> Token {
+        let mut token = *self;
+        let (s, e) = self.span();
+        let span = (s.max(start), e.min(end));
+        match &mut token {
+            Token::RightCaret(s)
+            | Token::Hash(s)
+            | Token::Dash(s)
+            | Token::Asterisk(s)
+            | Token::Plus(s)
+            | Token::NumDot(s)
+            | Token::NumParen(s)
+            | Token::Tilde(s)
+            | Token::Dollar(s)
+            | Token::Bang(s)
+            | Token::LeftBracket(s)
+            | Token::RightBracket(s)
+            | Token::Equals(s)
+            | Token::Caret(s)
+            | Token::LeftAngle(s)
+            | Token::LeftBrace(s)
+            | Token::RightBrace(s)
+            | Token::Plaintext(s)
+            | Token::Whitespace(s)
+            | Token::Newline(s) => *s = span,
+        }
+        token
+    }
 }
 
 pub struct Tokenizer<'a> {
     start: usize,
-    source: &'a str,
+    source: &'a [u8],
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(start: usize, source: &'a str) -> Self {
-        Tokenizer { start, source }
+        Tokenizer {
+            start,
+            source: source.as_bytes(),
+        }
+    }
+
+    /// The end of the run of bytes in `table` from `p`.
+    fn run(&self, p: usize, table: &[bool; 256]) -> usize {
+        self.source[p..]
+            .iter()
+            .position(|&b| !table[b as usize])
+            .map_or(self.source.len(), |n| p + n)
+    }
+
+    /// The end of the run of `byte` from `p`.
+    fn repeat(&self, p: usize, byte: u8) -> usize {
+        self.source[p..]
+            .iter()
+            .position(|&b| b != byte)
+            .map_or(self.source.len(), |n| p + n)
+    }
+
+    /// The end of the plaintext from `p`, found by skipping to the next
+    /// special byte.
+    fn plaintext(&self, mut p: usize) -> usize {
+        loop {
+            p = match self.source[p..].iter().position(|&b| SPECIAL[b as usize]) {
+                Some(n) => p + n,
+                None => return self.source.len(),
+            };
+            if self.source[p] != b'!' || self.source.get(p + 1) == Some(&b'[') {
+                return p;
+            }
+            p += 1;
+        }
     }
 }
 
@@ -59,103 +147,50 @@ impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut p = self.start;
-        let mut state = TokenizerState::Unset;
-        let mut result = None;
-
-        while state != TokenizerState::Done {
-            let (new_state, new_p) = match (state, self.source.get(p..p + 1)) {
-                // Whitespace
-                (TokenizerState::Whitespace, Some(c)) if WHITESPACE_CHARS.contains(&c) => {
-                    (TokenizerState::Whitespace, p + 1)
-                }
-                (TokenizerState::Whitespace, _) => {
-                    result = Some(Token::Whitespace((self.start, p)));
-                    (TokenizerState::Done, p)
-                }
-                // Plaintext
-                (TokenizerState::Plaintext, Some(c)) if WHITESPACE_CHARS.contains(&c) => {
-                    result = Some(Token::Plaintext((self.start, p)));
-                    (TokenizerState::Done, p)
-                }
-                (TokenizerState::Plaintext, Some("\n")) => {
-                    result = Some(Token::Plaintext((self.start, p)));
-                    (TokenizerState::Done, p)
-                }
-                (TokenizerState::Plaintext, Some(_)) => (TokenizerState::Plaintext, p + 1),
-                (TokenizerState::Plaintext, None) => {
-                    result = Some(Token::Plaintext((self.start, p)));
-                    (TokenizerState::Done, p)
-                }
-                // Number
-                (TokenizerState::Number, Some(c)) if NUMBER_CHARS.contains(&c) => {
-                    (TokenizerState::Number, p + 1)
+        let start = self.start;
+        let byte = *self.source.get(start)?;
+        let (token, end): (fn(Span) -> Token, usize) = match byte {
+            b' ' | b'\t' => (Token::Whitespace, self.run(start, &WHITESPACE)),
+            b'\n' => (Token::Newline, start + 1),
+            b'>' => (Token::RightCaret, start + 1),
+            b'<' => (Token::LeftAngle, start + 1),
+            b'{' => (Token::LeftBrace, start + 1),
+            b'}' => (Token::RightBrace, start + 1),
+            b'-' => (Token::Dash, start + 1),
+            b'*' => (Token::Asterisk, start + 1),
+            b'+' => (Token::Plus, start + 1),
+            b'#' => (Token::Hash, self.repeat(start, byte)),
+            b'~' => (Token::Tilde, self.repeat(start, byte)),
+            b'$' => (Token::Dollar, self.repeat(start, byte)),
+            b'=' => (Token::Equals, self.repeat(start, byte)),
+            b'^' => (Token::Caret, self.repeat(start, byte)),
+            b'[' => (Token::LeftBracket, self.repeat(start, byte)),
+            b']' => (Token::RightBracket, self.repeat(start, byte)),
+            b'!' if self.source.get(start + 1) == Some(&b'[') => (Token::Bang, start + 1),
+            b'0'..=b'9' => {
+                // Numbers followed by anything else are plain text
+                let p = self.run(start, &DIGITS);
+                match self.source.get(p) {
+                    Some(b'.') => (Token::NumDot, p + 1),
+                    Some(b')') => (Token::NumParen, p + 1),
+                    _ => (Token::Plaintext, self.plaintext(p)),
                 }
-                (TokenizerState::Number, Some(".")) => {
-                    result = Some(Token::NumDot((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                (TokenizerState::Number, Some(")")) => {
-                    result = Some(Token::NumParen((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                (TokenizerState::Number, _) => (TokenizerState::Plaintext, p),
-                // Hash
-                (TokenizerState::Hash, Some("#")) => (TokenizerState::Hash, p + 1),
-                (TokenizerState::Hash, _) => {
-                    result = Some(Token::Hash((self.start, p)));
-                    (TokenizerState::Done, p)
-                }
-                // Dash
-                (TokenizerState::Unset, Some("-")) => {
-                    result = Some(Token::Dash((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                // Asterisk
-                (TokenizerState::Unset, Some("*")) => {
-                    result = Some(Token::Asterisk((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                // Plus
-                (TokenizerState::Unset, Some("+")) => {
-                    result = Some(Token::Plus((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                // Unset
-                (TokenizerState::Unset, Some(c)) if WHITESPACE_CHARS.contains(&c) => {
-                    (TokenizerState::Whitespace, p + 1)
-                }
-                (TokenizerState::Unset, Some(c)) if NUMBER_CHARS.contains(&c) => {
-                    (TokenizerState::Number, p + 1)
-                }
-                (TokenizerState::Unset, Some("\n")) => {
-                    result = Some(Token::Newline((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                (TokenizerState::Unset, Some(">")) => {
-                    result = Some(Token::RightCaret((self.start, p + 1)));
-                    (TokenizerState::Done, p + 1)
-                }
-                (TokenizerState::Unset, Some("#")) => {
-                    result = Some(Token::Hash((self.start, p + 1)));
-                    (TokenizerState::Hash, p + 1)
-                }
-                (TokenizerState::Unset, Some(_)) => (TokenizerState::Plaintext, p + 1),
-                // Done
-                _ => (TokenizerState::Done, p),
-            };
-            state = new_state;
-            p = new_p;
-        }
-
-        self.start = p;
-        result
+            }
+            _ => (Token::Plaintext, self.plaintext(start + 1)),
+        };
+        self.start = end;
+        Some(token((start, end)))
     }
 }
 
 #[cfg(test)]
 mod test {
+    extern crate test;
+
     use super::*;
+    use crate::markdown::parse::test::large;
+    use proptest::prelude::*;
+    use test::Bencher;
 
     #[test]
     fn test_plaintext() {
@@ -224,4 +259,96 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_tilde() {
+        let tokenizer = Tokenizer::new(0, "~~Done~~ ~x");
+        let result = tokenizer.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            result,
+            vec![
+                Token::Tilde((0, 2)),
+                Token::Plaintext((2, 6)),
+                Token::Tilde((6, 8)),
+                Token::Whitespace((8, 9)),
+                Token::Tilde((9, 10)),
+                Token::Plaintext((10, 11)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brackets() {
+        let tokenizer = Tokenizer::new(0, "Hi! ![[A|b]]");
+        let result = tokenizer.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            result,
+            vec![
+                Token::Plaintext((0, 3)),
+                Token::Whitespace((3, 4)),
+                Token::Bang((4, 5)),
+                Token::LeftBracket((5, 7)),
+                Token::Plaintext((7, 10)),
+                Token::RightBracket((10, 12)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_braces() {
+        let tokenizer = Tokenizer::new(0, "Hi{#a .b}");
+        let result = tokenizer.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            result,
+            vec![
+                Token::Plaintext((0, 2)),
+                Token::LeftBrace((2, 3)),
+                Token::Hash((3, 4)),
+                Token::Plaintext((4, 5)),
+                Token::Whitespace((5, 6)),
+                Token::Plaintext((6, 8)),
+                Token::RightBrace((8, 9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let tokenizer = Tokenizer::new(0, "Grüße ~日本~");
+        let result = tokenizer.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            result,
+            vec![
+                Token::Plaintext((0, 7)),
+                Token::Whitespace((7, 8)),
+                Token::Tilde((8, 9)),
+                Token::Plaintext((9, 15)),
+                Token::Tilde((15, 16)),
+            ]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_spans(source in ".*") {
+            // Tokens cover the source in order and end on char boundaries
+            let mut end = 0;
+            for token in Tokenizer::new(0, &source) {
+                let (s, e) = token.span();
+                prop_assert!(s == end && s < e && source.is_char_boundary(e));
+                end = e;
+            }
+            prop_assert_eq!(end, source.len());
+        }
+    }
+
+    #[bench]
+    fn bench_tokenize(b: &mut Bencher) {
+        let source = large();
+        b.iter(|| Tokenizer::new(0, &source).count());
+    }
 }
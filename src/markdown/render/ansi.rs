@@ -0,0 +1,634 @@
+use crate::markdown::render::{list_start, narrower, render_with, Renderer, Resolver};
+use crate::markdown::{Kind, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Wrap text at this many columns, 0 leaves wrapping to the terminal
+    pub width: usize,
+    /// Style text with SGR escape sequences
+    pub color: bool,
+    /// Make wiki links clickable with OSC 8 escape sequences
+    pub hyperlinks: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 80,
+            color: true,
+            hyperlinks: false,
+        }
+    }
+}
+
+// SGR parameters
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const UNDERLINE: &str = "4";
+const INVERSE: &str = "7";
+const STRIKE: &str = "9";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const BLUE: &str = "34";
+const CYAN: &str = "36";
+const HIGHLIGHT: &str = "30;43";
+
+const BAR: &str = "\u{2502}";
+const BULLET: &str = "\u{2022}";
+
+/// Text written in one style.
+#[derive(Debug, Clone, PartialEq)]
+struct Run {
+    style: Vec<&'static str>,
+    link: Option<String>,
+    text: String,
+}
+
+impl Run {
+    fn continues(&self, style: &[&'static str], link: Option<&str>) -> bool {
+        self.style == style && self.link.as_deref() == link
+    }
+}
+
+/// What separates a word from the one before it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    None,
+    Space,
+    Hard,
+}
+
+/// Collects the words of inline content, a word may span several styles.
+#[derive(Default)]
+struct Words {
+    words: Vec<(Break, Vec<Run>)>,
+    word: Vec<Run>,
+    before: Option<Break>,
+    pending: Option<Break>,
+    spaces: usize,
+    line_start: bool,
+}
+
+impl Words {
+    fn text(&mut self, text: &str, style: &[&'static str], link: Option<&str>, plain: bool) {
+        for c in text.chars() {
+            match c {
+                '\n' if plain => {
+                    let b = match self.spaces >= 2 {
+                        true => Break::Hard,
+                        false => Break::Space,
+                    };
+                    self.pending = self.pending.max(Some(b));
+                    self.spaces = 0;
+                    self.line_start = true;
+                }
+                // Block quote markers of continuation lines
+                '>' if plain && self.line_start => {}
+                c if c.is_whitespace() => {
+                    self.pending = self.pending.max(Some(Break::Space));
+                    self.spaces += if c == ' ' { 1 } else { 0 };
+                }
+                // Escape sequences in the source must not reach the terminal
+                c if c.is_control() => {}
+                c => {
+                    if let Some(b) = self.pending.take() {
+                        if !self.word.is_empty() {
+                            let word = std::mem::take(&mut self.word);
+                            self.words.push((self.before.unwrap_or(Break::None), word));
+                            self.before = Some(b);
+                        }
+                    }
+                    self.spaces = 0;
+                    self.line_start = false;
+                    match self.word.last_mut() {
+                        Some(run) if run.continues(style, link) => run.text.push(c),
+                        _ => self.word.push(Run {
+                            style: style.to_vec(),
+                            link: link.map(str::to_string),
+                            text: c.to_string(),
+                        }),
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<(Break, Vec<Run>)> {
+        if !self.word.is_empty() {
+            let word = std::mem::take(&mut self.word);
+            self.words.push((self.before.unwrap_or(Break::None), word));
+        }
+        self.words
+    }
+}
+
+/// Lines rendered so far inside a container, and how its blocks are laid
+/// out.
+struct Frame {
+    lines: Vec<String>,
+    tight: bool,
+    width: usize,
+}
+
+pub struct Writer<'a> {
+    resolver: Option<&'a dyn Resolver>,
+    options: &'a Options,
+    // Every open container, innermost last
+    frames: Vec<Frame>,
+    // Start of every open list, if ordered, the items written in it and
+    // whether it is loose
+    lists: Vec<(Option<u64>, u64, bool)>,
+    // Words of the heading or paragraph being written, and their style
+    words: Option<Words>,
+    style: Vec<&'static str>,
+    out: String,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>, options: &'a Options) -> Self {
+        Writer {
+            resolver,
+            options,
+            frames: vec![],
+            lists: vec![],
+            words: None,
+            style: vec![],
+            out: String::new(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.frames.last().map_or(self.options.width, |f| f.width)
+    }
+
+    fn push_frame(&mut self, tight: bool, width: usize) {
+        self.frames.push(Frame {
+            lines: vec![],
+            tight,
+            width,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Vec<String> {
+        self.frames.pop().map(|f| f.lines).unwrap_or_default()
+    }
+
+    /// Adds the lines of a block, after a blank line unless the container
+    /// is tight.
+    fn block(&mut self, block: Vec<String>) {
+        match self.frames.last_mut() {
+            Some(frame) if !block.is_empty() => {
+                if !frame.lines.is_empty() && !frame.tight {
+                    frame.lines.push(String::new());
+                }
+                frame.lines.extend(block);
+            }
+            _ => {}
+        }
+    }
+
+    /// The marker of the next item in the innermost list.
+    fn marker(&self) -> String {
+        match self.lists.last() {
+            Some(&(Some(n), i, _)) => format!("{}. ", n + i),
+            _ => format!("{} ", BULLET),
+        }
+    }
+
+    fn enter_list(&mut self, start: Option<u64>, loose: bool) {
+        self.lists.push((start, 0, loose));
+        self.push_frame(false, self.width());
+    }
+
+    fn leave_list(&mut self) {
+        self.lists.pop();
+        let lines = self.pop_frame();
+        self.block(lines);
+    }
+
+    fn enter_text(&mut self, style: &[&'static str]) {
+        self.words = Some(Words::default());
+        self.style = style.to_vec();
+    }
+
+    /// Fills the words of a heading or paragraph into lines up to the width.
+    fn leave_text(&mut self) {
+        let words = self.words.take().unwrap_or_default();
+        let width = self.width();
+        let mut lines = vec![];
+        let mut line: Vec<Run> = vec![];
+        let mut columns = 0;
+        for (b, word) in words.finish() {
+            let length: usize = word.iter().map(|r| r.text.chars().count()).sum();
+            let fits = width == 0 || columns + 1 + length <= width;
+            match b {
+                Break::None => {}
+                Break::Space if fits => {
+                    // The space takes the style of the words it joins
+                    let first = &word[0];
+                    match line.last_mut() {
+                        Some(run) if run.continues(&first.style, first.link.as_deref()) => {
+                            run.text.push(' ')
+                        }
+                        _ => line.push(Run {
+                            style: vec![],
+                            link: None,
+                            text: " ".to_string(),
+                        }),
+                    }
+                    columns += 1;
+                }
+                _ => {
+                    lines.push(self.paint(&std::mem::take(&mut line)));
+                    columns = 0;
+                }
+            }
+            columns += length;
+            for run in word {
+                match line.last_mut() {
+                    Some(last) if last.continues(&run.style, run.link.as_deref()) => {
+                        last.text.push_str(&run.text)
+                    }
+                    _ => line.push(run),
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push(self.paint(&line));
+        }
+        self.block(lines);
+    }
+
+    fn text(&mut self, text: &str, link: Option<&str>, plain: bool) {
+        if let Some(words) = &mut self.words {
+            words.text(text, &self.style, link, plain);
+        }
+    }
+
+    fn styled(&mut self, code: &'static str, text: &str) {
+        self.style.push(code);
+        self.text(text, None, false);
+        self.style.pop();
+    }
+
+    /// Writes a line of runs, switching styles and links between them and
+    /// resetting both at the end.
+    fn paint(&self, runs: &[Run]) -> String {
+        let mut out = String::new();
+        let mut style: &[&str] = &[];
+        let mut link: Option<&str> = None;
+        for run in runs {
+            if self.options.hyperlinks && run.link.as_deref() != link {
+                link = run.link.as_deref();
+                out.push_str(&format!("\x1b]8;;{}\x1b\\", link.unwrap_or_default()));
+            }
+            if self.options.color && run.style != style {
+                if !style.is_empty() {
+                    out.push_str("\x1b[0m");
+                }
+                style = &run.style;
+                if !style.is_empty() {
+                    out.push_str(&format!("\x1b[{}m", style.join(";")));
+                }
+            }
+            out.push_str(&run.text);
+        }
+        if !style.is_empty() {
+            out.push_str("\x1b[0m");
+        }
+        if link.is_some() {
+            out.push_str("\x1b]8;;\x1b\\");
+        }
+        out
+    }
+}
+
+impl<'a> Renderer for Writer<'a> {
+    type Output = String;
+
+    fn finish(&mut self) -> String {
+        self.frames.clear();
+        self.lists.clear();
+        std::mem::take(&mut self.out)
+    }
+
+    fn enter_document(&mut self, _source: &str, _node: &Node) {
+        self.push_frame(false, self.options.width);
+    }
+
+    fn leave_document(&mut self, _source: &str, _node: &Node) {
+        self.out = self.pop_frame().join("\n");
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn enter_block_quote(&mut self, _source: &str, _node: &Node) {
+        self.push_frame(false, narrower(self.width(), 2));
+    }
+
+    fn leave_block_quote(&mut self, _source: &str, _node: &Node) {
+        let bar = self.paint(&[Run {
+            style: vec![DIM],
+            link: None,
+            text: BAR.to_string(),
+        }]);
+        let lines = self
+            .pop_frame()
+            .into_iter()
+            .map(|l| match l.is_empty() {
+                true => bar.clone(),
+                false => format!("{} {}", bar, l),
+            })
+            .collect();
+        self.block(lines);
+    }
+
+    fn enter_unordered_list(&mut self, _source: &str, _node: &Node, loose: bool) {
+        self.enter_list(None, loose);
+    }
+
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.leave_list();
+    }
+
+    fn enter_ordered_list(&mut self, source: &str, node: &Node, loose: bool) {
+        let start = list_start(source, node.span.0).unwrap_or(1);
+        self.enter_list(Some(start), loose);
+    }
+
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.leave_list();
+    }
+
+    fn enter_list_item(&mut self, _source: &str, _node: &Node) {
+        let loose = self.lists.last().is_some_and(|&(_, _, loose)| loose);
+        let columns = self.marker().chars().count();
+        self.push_frame(!loose, narrower(self.width(), columns));
+    }
+
+    fn leave_list_item(&mut self, _source: &str, _node: &Node) {
+        let marker = self.marker();
+        let mut body = self.pop_frame().into_iter();
+        let (_, i, loose) = self.lists.last().copied().unwrap_or((None, 0, false));
+        if let Some(list) = self.lists.last_mut() {
+            list.1 += 1;
+        }
+        let mut lines = vec![];
+        if i > 0 && loose {
+            lines.push(String::new());
+        }
+        // Wrapped lines hang below the text of the item
+        let indent = " ".repeat(marker.chars().count());
+        lines.push(
+            format!("{}{}", marker, body.next().unwrap_or_default())
+                .trim_end()
+                .to_string(),
+        );
+        for line in body {
+            match line.is_empty() {
+                true => lines.push(line),
+                false => lines.push(format!("{}{}", indent, line)),
+            }
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.lines.extend(lines);
+        }
+    }
+
+    fn enter_heading(&mut self, _source: &str, _node: &Node, _level: usize) {
+        self.enter_text(&[BOLD]);
+    }
+
+    fn leave_heading(&mut self, _source: &str, _node: &Node, _level: usize) {
+        self.leave_text();
+    }
+
+    fn enter_paragraph(&mut self, _source: &str, _node: &Node) {
+        self.enter_text(&[]);
+    }
+
+    fn leave_paragraph(&mut self, _source: &str, _node: &Node) {
+        self.leave_text();
+    }
+
+    fn plaintext(&mut self, source: &str, node: &Node) {
+        self.text(&source[node.span.0..node.span.1], None, true);
+    }
+
+    fn whitespace(&mut self, source: &str, node: &Node) {
+        self.text(&source[node.span.0..node.span.1], None, true);
+    }
+
+    fn enter_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.style.push(STRIKE);
+    }
+
+    fn leave_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.style.pop();
+    }
+
+    // Rendered like a code span
+    fn math_inline(&mut self, source: &str, node: &Node) {
+        let (start, end) = node.span;
+        self.styled(INVERSE, &source[start + 1..end - 1]);
+    }
+
+    fn wiki_link(&mut self, source: &str, node: &Node) {
+        let (embed, target, anchor, label) = match node.kind {
+            Kind::WikiLink {
+                embed,
+                target,
+                anchor,
+                label,
+            } => (embed, target, anchor, label),
+            _ => unreachable!(),
+        };
+        let resolution = self
+            .resolver
+            .and_then(|r| r.resolve(&source[target.0..target.1]));
+        let mut url = match &resolution {
+            Some(r) => r.url.clone(),
+            None => source[target.0..target.1].to_string(),
+        };
+        if let Some((s, e)) = anchor {
+            url = format!("{}#{}", url, &source[s..e]);
+        }
+        let (text_start, text_end) = label.unwrap_or((target.0, anchor.unwrap_or(target).1));
+        let codes: &[&'static str] = match (embed, resolution) {
+            (true, _) => &[DIM],
+            (false, Some(r)) if !r.exists => &[UNDERLINE, RED],
+            (false, _) => &[UNDERLINE, BLUE],
+        };
+        // A control character could end the escape sequence early
+        let clickable = self.options.hyperlinks && !url.contains(char::is_control);
+        let link = clickable.then_some(url.as_str());
+        let depth = self.style.len();
+        self.style.extend_from_slice(codes);
+        self.text(&source[text_start..text_end], link, false);
+        self.style.truncate(depth);
+    }
+
+    fn mention(&mut self, source: &str, node: &Node) {
+        self.styled(CYAN, &source[node.span.0..node.span.1]);
+    }
+
+    fn hashtag(&mut self, source: &str, node: &Node) {
+        self.styled(CYAN, &source[node.span.0..node.span.1]);
+    }
+
+    fn emoji(&mut self, _source: &str, _node: &Node, emoji: &'static str) {
+        self.text(emoji, None, false);
+    }
+
+    fn enter_highlight(&mut self, _source: &str, _node: &Node) {
+        self.style.push(HIGHLIGHT);
+    }
+
+    fn leave_highlight(&mut self, _source: &str, _node: &Node) {
+        self.style.pop();
+    }
+
+    // Without color changes are marked like wdiff output
+    fn enter_critic_addition(&mut self, _source: &str, _node: &Node) {
+        match self.options.color {
+            true => self.style.push(GREEN),
+            false => self.text("{+", None, false),
+        }
+    }
+
+    fn leave_critic_addition(&mut self, _source: &str, _node: &Node) {
+        match self.options.color {
+            true => self.style.truncate(self.style.len().saturating_sub(1)),
+            false => self.text("+}", None, false),
+        }
+    }
+
+    fn enter_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        match self.options.color {
+            true => self.style.extend([RED, STRIKE]),
+            false => self.text("[-", None, false),
+        }
+    }
+
+    fn leave_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        match self.options.color {
+            true => self.style.truncate(self.style.len().saturating_sub(2)),
+            false => self.text("-]", None, false),
+        }
+    }
+
+    fn enter_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.style.push(HIGHLIGHT);
+    }
+
+    fn leave_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.style.pop();
+    }
+
+    fn critic_comment(&mut self, source: &str, node: &Node) {
+        let (start, end) = node.span;
+        self.styled(DIM, &source[start + 3..end - 3]);
+    }
+
+    fn smart_punctuation(&mut self, _source: &str, _node: &Node, replacement: &'static str) {
+        self.text(replacement, None, false);
+    }
+}
+
+/// Writes the document as styled text for a terminal.
+pub fn render(
+    source: &str,
+    node: &Node,
+    resolver: Option<&dyn Resolver>,
+    options: &Options,
+) -> String {
+    render_with(&mut Writer::new(resolver, options), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::render::test::Pages;
+    use crate::markdown::{parse, Options as ParseOptions};
+    use insta::assert_snapshot;
+
+    const SOURCE: &str = "# Release notes
+
+Some ~~old~~ text with $x^2$ math, a #tag and a [[Page#Part|link]] that
+wraps across lines.  
+Hard break here.
+
+> Quoted {++new++}{--old--} text
+> that continues lazily.
+>
+> Second paragraph.
+
+* A list item long enough to wrap onto a second line
+  * nested
+* [[Missing]]
+
+Then
+
+3. three
+4. four
+";
+
+    fn ansi(source: &str, options: &Options) -> String {
+        let parse_options = ParseOptions {
+            math: true,
+            hashtags: true,
+            highlight: true,
+            critic_markup: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse(source, &parse_options);
+        // Escapes are spelled out to keep the snapshots readable
+        render(source, &doc, Some(&Pages), options).replace('\x1b', "\\e")
+    }
+
+    #[test]
+    fn test_plain() {
+        let options = Options {
+            width: 30,
+            color: false,
+            hyperlinks: false,
+        };
+        assert_snapshot!(ansi(SOURCE, &options));
+    }
+
+    #[test]
+    fn test_color() {
+        assert_snapshot!(ansi(SOURCE, &Options::default()));
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let options = Options {
+            width: 0,
+            color: false,
+            hyperlinks: true,
+        };
+        assert_snapshot!(ansi(SOURCE, &options));
+    }
+
+    #[test]
+    fn test_control_characters() {
+        let options = Options {
+            width: 0,
+            color: false,
+            hyperlinks: true,
+        };
+        assert_eq!(
+            ansi("a\x1b[2Jb\x07 [[x\x1by]] [[z]]", &options),
+            "a[2Jb xy \\e]8;;/wiki/z\\e\\z\\e]8;;\\e\\\n"
+        );
+    }
+
+    #[test]
+    fn test_styles_join() {
+        let options = Options::default();
+        assert_eq!(ansi("a ==b c== d", &options), "a \\e[30;43mb c\\e[0m d\n");
+        assert_eq!(ansi("", &options), "");
+    }
+}
@@ -0,0 +1,477 @@
+//! A compact encoding of the `render::json` tree into a flat byte buffer,
+//! cheaper to produce than JSON and transferable between threads. It is
+//! read back by js/decode.js.
+//!
+//! All integers are LEB128 varints unless noted, `signed` ones zigzag
+//! encoded first. The buffer holds:
+//!
+//! * the format version byte
+//! * the string table, a count then each string as a length and UTF-8
+//! * the document node
+//!
+//! A node is its kind code, a byte of `FLAG_*` bits, its signed start
+//! relative to the start of its parent, its length, then its merkle and
+//! position as eight little endian bytes each. The parts named by the flags follow in flag order,
+//! strings written as indexes into the table and optional ones offset by one
+//! so that zero means none:
+//!
+//! * text, a string
+//! * children, a count then each node
+//! * link, a byte of `LINK_*` bits, the target as signed start relative to
+//!   the node and length, the anchor and label when present the same way,
+//!   then the url when present
+//! * html, its kind code, optional name and self closing byte
+//! * attributes, signed start relative to the node and length, optional
+//!   id, class count and classes, pair count and pairs
+//! * version, only on the root
+//!
+//! Lists set `FLAG_LIST` and, when loose, `FLAG_LOOSE` without anything
+//! following.
+
+use std::collections::HashMap;
+
+use crate::markdown::render::json::{Json, A, H, L, N};
+use crate::markdown::render::{render_with, Renderer, Resolver};
+use crate::markdown::Node;
+
+pub const VERSION: u8 = 4;
+
+const FLAG_TEXT: u8 = 1;
+const FLAG_CHILDREN: u8 = 2;
+const FLAG_LINK: u8 = 4;
+const FLAG_HTML: u8 = 8;
+const FLAG_ATTRIBUTES: u8 = 16;
+const FLAG_VERSION: u8 = 32;
+const FLAG_LIST: u8 = 64;
+const FLAG_LOOSE: u8 = 128;
+
+const LINK_EMBED: u8 = 1;
+const LINK_ANCHOR: u8 = 2;
+const LINK_LABEL: u8 = 4;
+const LINK_URL: u8 = 8;
+const LINK_EXISTS: u8 = 16;
+const LINK_RESOLVED: u8 = 32;
+
+#[derive(Default)]
+struct Encoder {
+    strings: Vec<String>,
+    indexes: HashMap<String, u64>,
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn node(&mut self, n: &N, parent: usize) {
+        let (start, end) = n.span;
+        let flags = [
+            (n.text.is_some(), FLAG_TEXT),
+            (n.children.is_some(), FLAG_CHILDREN),
+            (n.link.is_some(), FLAG_LINK),
+            (n.html.is_some(), FLAG_HTML),
+            (n.attributes.is_some(), FLAG_ATTRIBUTES),
+            (n.version.is_some(), FLAG_VERSION),
+            (n.loose.is_some(), FLAG_LIST),
+            (n.loose == Some(true), FLAG_LOOSE),
+        ];
+        let flags = flags.iter().filter(|f| f.0).fold(0, |a, f| a | f.1);
+        self.varint(i64::from(n.kind) as u64);
+        self.out.push(flags);
+        self.signed(start as i64 - parent as i64);
+        self.varint((end - start) as u64);
+        self.out.extend_from_slice(&n.merkle.0.to_le_bytes());
+        self.out.extend_from_slice(&n.position.0.to_le_bytes());
+        if let Some(text) = &n.text {
+            let i = self.string(text);
+            self.varint(i);
+        }
+        if let Some(children) = &n.children {
+            self.varint(children.len() as u64);
+            for child in children {
+                self.node(child, start);
+            }
+        }
+        if let Some(link) = &n.link {
+            self.link(link, start);
+        }
+        if let Some(html) = &n.html {
+            self.html(html);
+        }
+        if let Some(attributes) = &n.attributes {
+            self.attributes(attributes, start);
+        }
+        if let Some(version) = n.version {
+            self.varint(version as u64);
+        }
+    }
+
+    fn link(&mut self, link: &L, start: usize) {
+        let flags = [
+            (link.embed, LINK_EMBED),
+            (link.anchor.is_some(), LINK_ANCHOR),
+            (link.label.is_some(), LINK_LABEL),
+            (link.url.is_some(), LINK_URL),
+            (link.exists == Some(true), LINK_EXISTS),
+            (link.exists.is_some(), LINK_RESOLVED),
+        ];
+        self.out
+            .push(flags.iter().filter(|f| f.0).fold(0, |a, f| a | f.1));
+        let spans = std::iter::once(link.target)
+            .chain(link.anchor)
+            .chain(link.label);
+        for (s, e) in spans {
+            self.signed(s as i64 - start as i64);
+            self.varint((e - s) as u64);
+        }
+        if let Some(url) = &link.url {
+            let i = self.string(url);
+            self.varint(i);
+        }
+    }
+
+    fn html(&mut self, html: &H) {
+        self.out.push(html.kind as u8);
+        let name = html.name.as_ref().map(|s| self.string(s));
+        self.optional(name);
+        self.out.push(html.self_closing as u8);
+    }
+
+    fn attributes(&mut self, attributes: &A, start: usize) {
+        let (s, e) = attributes.span;
+        self.signed(s as i64 - start as i64);
+        self.varint((e - s) as u64);
+        let id = attributes.id.as_ref().map(|s| self.string(s));
+        self.optional(id);
+        self.varint(attributes.classes.len() as u64);
+        for class in &attributes.classes {
+            let i = self.string(class);
+            self.varint(i);
+        }
+        self.varint(attributes.pairs.len() as u64);
+        for (key, value) in &attributes.pairs {
+            let (k, v) = (self.string(key), self.string(value));
+            self.varint(k);
+            self.varint(v);
+        }
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(&i) = self.indexes.get(s) {
+            return i;
+        }
+        let i = self.strings.len() as u64;
+        self.strings.push(s.to_string());
+        self.indexes.insert(s.to_string(), i);
+        i
+    }
+
+    fn optional(&mut self, i: Option<u64>) {
+        self.varint(i.map_or(0, |i| i + 1));
+    }
+
+    fn signed(&mut self, n: i64) {
+        self.varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn varint(&mut self, n: u64) {
+        write_varint(&mut self.out, n);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Encodes the tree, which would otherwise be rendered by `render::json`.
+pub fn encode(n: &N) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.node(n, 0);
+    let mut result = Vec::with_capacity(encoder.out.len() + encoder.strings.len() * 8 + 8);
+    result.push(VERSION);
+    write_varint(&mut result, encoder.strings.len() as u64);
+    for s in &encoder.strings {
+        write_varint(&mut result, s.len() as u64);
+        result.extend_from_slice(s.as_bytes());
+    }
+    result.extend_from_slice(&encoder.out);
+    result
+}
+
+/// Builds the JSON tree and encodes it once every node has been left.
+pub struct Binary<'a>(Json<'a>);
+
+impl<'a> Binary<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>) -> Self {
+        Binary(Json::new(resolver))
+    }
+}
+
+impl<'a> Renderer for Binary<'a> {
+    type Output = Vec<u8>;
+
+    fn enter(&mut self, source: &str, node: &Node) {
+        self.0.enter(source, node);
+    }
+
+    fn leave(&mut self, source: &str, node: &Node) {
+        self.0.leave(source, node);
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        encode(&self.0.tree())
+    }
+}
+
+pub fn render(source: &str, node: &Node, resolver: Option<&dyn Resolver>) -> Vec<u8> {
+    render_with(&mut Binary::new(resolver), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::parse;
+    use crate::markdown::parse::test::options;
+    use crate::markdown::render::json::{self, Hash, HtmlK, K};
+    use crate::markdown::render::test::{Pages, SOURCE};
+    use serde_json::{json, Map, Value};
+    use std::convert::{TryFrom, TryInto};
+
+    struct Decoder<'a> {
+        bytes: &'a [u8],
+        p: usize,
+        strings: Vec<&'a str>,
+    }
+
+    impl<'a> Decoder<'a> {
+        fn node(&mut self, parent: i64) -> Option<Value> {
+            let kind = K::try_from(self.varint()? as i64).ok()?;
+            let flags = self.byte()?;
+            let start = parent + self.signed()?;
+            let end = start + self.varint()? as i64;
+            let merkle = Hash(u64::from_le_bytes(self.take(8)?.try_into().ok()?));
+            let position = Hash(u64::from_le_bytes(self.take(8)?.try_into().ok()?));
+            let mut node = Map::new();
+            node.insert("kind".into(), json!(kind));
+            node.insert("span".into(), json!([start, end]));
+            node.insert("merkle".into(), json!(merkle));
+            node.insert("position".into(), json!(position));
+            let text = match flags & FLAG_TEXT {
+                0 => Value::Null,
+                _ => json!(self.string()?),
+            };
+            let children = match flags & FLAG_CHILDREN {
+                0 => Value::Null,
+                _ => {
+                    let count = self.varint()?;
+                    let mut children = vec![];
+                    for _ in 0..count {
+                        children.push(self.node(start)?);
+                    }
+                    Value::Array(children)
+                }
+            };
+            node.insert("children".into(), children);
+            node.insert("text".into(), text);
+            if flags & FLAG_LINK != 0 {
+                node.insert("link".into(), self.link(start)?);
+            }
+            if flags & FLAG_HTML != 0 {
+                node.insert("html".into(), self.html()?);
+            }
+            if flags & FLAG_ATTRIBUTES != 0 {
+                node.insert("attributes".into(), self.attributes(start)?);
+            }
+            if flags & FLAG_VERSION != 0 {
+                node.insert("version".into(), json!(self.varint()?));
+            }
+            if flags & FLAG_LIST != 0 {
+                node.insert("loose".into(), json!(flags & FLAG_LOOSE != 0));
+            }
+            Some(Value::Object(node))
+        }
+
+        fn link(&mut self, start: i64) -> Option<Value> {
+            let flags = self.byte()?;
+            let target = self.span(start)?;
+            let anchor = match flags & LINK_ANCHOR {
+                0 => Value::Null,
+                _ => self.span(start)?,
+            };
+            let label = match flags & LINK_LABEL {
+                0 => Value::Null,
+                _ => self.span(start)?,
+            };
+            let url = match flags & LINK_URL {
+                0 => Value::Null,
+                _ => json!(self.string()?),
+            };
+            let exists = match flags & LINK_RESOLVED {
+                0 => Value::Null,
+                _ => json!(flags & LINK_EXISTS != 0),
+            };
+            Some(json!({
+                "embed": flags & LINK_EMBED != 0,
+                "target": target,
+                "anchor": anchor,
+                "label": label,
+                "url": url,
+                "exists": exists,
+            }))
+        }
+
+        fn html(&mut self) -> Option<Value> {
+            let kind = match self.byte()? {
+                0 => HtmlK::Open,
+                1 => HtmlK::Close,
+                2 => HtmlK::Comment,
+                3 => HtmlK::ProcessingInstruction,
+                4 => HtmlK::Declaration,
+                5 => HtmlK::Cdata,
+                _ => return None,
+            };
+            let name = self.optional()?;
+            let self_closing = self.byte()? != 0;
+            Some(json!({ "kind": kind, "name": name, "self_closing": self_closing }))
+        }
+
+        fn attributes(&mut self, start: i64) -> Option<Value> {
+            let span = self.span(start)?;
+            let id = self.optional()?;
+            let mut classes = vec![];
+            for _ in 0..self.varint()? {
+                classes.push(self.string()?);
+            }
+            let mut pairs = vec![];
+            for _ in 0..self.varint()? {
+                pairs.push((self.string()?, self.string()?));
+            }
+            Some(json!({ "span": span, "id": id, "classes": classes, "pairs": pairs }))
+        }
+
+        fn span(&mut self, start: i64) -> Option<Value> {
+            let s = start + self.signed()?;
+            Some(json!([s, s + self.varint()? as i64]))
+        }
+
+        fn string(&mut self) -> Option<&'a str> {
+            let i = self.varint()? as usize;
+            self.strings.get(i).copied()
+        }
+
+        fn optional(&mut self) -> Option<Option<&'a str>> {
+            match self.varint()? as usize {
+                0 => Some(None),
+                i => self.strings.get(i - 1).map(|&s| Some(s)),
+            }
+        }
+
+        fn signed(&mut self) -> Option<i64> {
+            let n = self.varint()?;
+            Some((n >> 1) as i64 ^ -((n & 1) as i64))
+        }
+
+        fn varint(&mut self) -> Option<u64> {
+            let mut n = 0;
+            for shift in (0..64).step_by(7) {
+                let b = self.byte()?;
+                n |= ((b & 0x7f) as u64) << shift;
+                if b & 0x80 == 0 {
+                    return Some(n);
+                }
+            }
+            None
+        }
+
+        fn byte(&mut self) -> Option<u8> {
+            let b = *self.bytes.get(self.p)?;
+            self.p += 1;
+            Some(b)
+        }
+
+        fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+            let bytes = self.bytes.get(self.p..self.p + n)?;
+            self.p += n;
+            Some(bytes)
+        }
+    }
+
+    /// Mirrors js/decode.js, giving the value `render::json` serializes or
+    /// `None` when the buffer is truncated, malformed or of another version.
+    fn decode(bytes: &[u8]) -> Option<Value> {
+        let mut decoder = Decoder {
+            bytes,
+            p: 0,
+            strings: vec![],
+        };
+        if decoder.byte()? != VERSION {
+            return None;
+        }
+        for _ in 0..decoder.varint()? {
+            let len = decoder.varint()? as usize;
+            let s = std::str::from_utf8(decoder.take(len)?).ok()?;
+            decoder.strings.push(s);
+        }
+        let node = decoder.node(0)?;
+        match decoder.p == bytes.len() {
+            true => Some(node),
+            false => None,
+        }
+    }
+
+    fn assert_round_trip(source: &str, resolver: Option<&dyn Resolver>) {
+        let expected = json::render(source, &parse(source, &options()), resolver);
+        let expected: Value = serde_json::from_str(&expected).unwrap();
+        let bytes = render(source, &parse(source, &options()), resolver);
+        assert_eq!(decode(&bytes), Some(expected), "{:?}", source);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip(SOURCE, None);
+        assert_round_trip(SOURCE, Some(&Pages));
+        assert_round_trip("", None);
+        assert_round_trip("\u{1f600} caf\u{e9}\n\n\n* a\n\n  b", None);
+    }
+
+    #[test]
+    fn test_compact() {
+        let source = SOURCE.repeat(20);
+        let json = json::render(&source, &parse(&source, &options()), None);
+        let bytes = render(&source, &parse(&source, &options()), None);
+        assert!(
+            bytes.len() * 3 < json.len(),
+            "{} {}",
+            bytes.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = render(SOURCE, &parse(SOURCE, &options()), None);
+        assert_eq!(decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode(&[bytes.as_slice(), &[0]].concat()), None);
+        assert_eq!(decode(&[VERSION + 1]), None);
+        assert_eq!(decode(&[]), None);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut encoder = Encoder::default();
+        let numbers = [0, 1, 127, 128, 300, u32::MAX as i64, i64::MAX, i64::MIN, -1];
+        for &n in &numbers {
+            encoder.signed(n);
+        }
+        let mut decoder = Decoder {
+            bytes: &encoder.out,
+            p: 0,
+            strings: vec![],
+        };
+        for &n in &numbers {
+            assert_eq!(decoder.signed(), Some(n));
+        }
+        assert_eq!(decoder.p, encoder.out.len());
+    }
+}
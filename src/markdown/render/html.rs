@@ -0,0 +1,575 @@
+use crate::markdown::render::{continuation, list_start, render_with, Renderer, Resolver};
+use crate::markdown::{Attributes, Html, Kind, Node};
+
+/// How a line break inside a paragraph is written.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SoftBreak {
+    #[default]
+    Newline,
+    Space,
+    Break,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Escape raw HTML, drop unsafe link URLs and keep only `id`, `class`
+    /// and `data-*` attributes
+    pub safe: bool,
+    /// How soft line breaks are written
+    pub soft_break: SoftBreak,
+    /// Add `data-sourcepos` attributes to blocks for scroll sync
+    pub source_pos: bool,
+}
+
+pub struct Writer<'a> {
+    resolver: Option<&'a dyn Resolver>,
+    options: &'a Options,
+    // Byte offset of the start of every line, for source positions
+    lines: Vec<usize>,
+    // Whether paragraphs in the innermost list or block quote are tight
+    tight: Vec<bool>,
+    out: String,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>, options: &'a Options) -> Self {
+        Writer {
+            resolver,
+            options,
+            lines: vec![],
+            tight: vec![],
+            out: String::new(),
+        }
+    }
+
+    /// Starts a new line unless the output is already at one.
+    fn cr(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Opens a list or block quote, whose paragraphs are tight or not.
+    fn container(&mut self, source: &str, tag: &str, node: &Node, extra: &str, tight: bool) {
+        self.cr();
+        self.open(source, tag, node, extra);
+        self.out.push('\n');
+        self.tight.push(tight);
+    }
+
+    // Paragraphs in tight lists are written without tags
+    fn is_tight(&self) -> bool {
+        self.tight.last() == Some(&true)
+    }
+
+    /// Writes text, turning line endings into soft or hard breaks.
+    fn text(&mut self, text: &str) {
+        let mut lines = text.split('\n').enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let line = match i {
+                0 => line,
+                _ => continuation(line),
+            };
+            if lines.peek().is_none() {
+                self.escape(line);
+                break;
+            }
+            let trimmed = line.trim_end_matches(' ');
+            self.escape(trimmed);
+            // Two or more trailing spaces make a hard break
+            match (line.len() - trimmed.len(), self.options.soft_break) {
+                (n, _) if n >= 2 => self.out.push_str("<br />\n"),
+                (_, SoftBreak::Newline) => self.out.push('\n'),
+                (_, SoftBreak::Space) => self.out.push(' '),
+                (_, SoftBreak::Break) => self.out.push_str("<br />\n"),
+            }
+        }
+    }
+
+    fn span(&mut self, class: &str, text: &str) {
+        self.out.push_str(&format!("<span class=\"{}\">", class));
+        self.escape(text);
+        self.out.push_str("</span>");
+    }
+
+    /// Writes an opening block tag with its source position and attributes.
+    fn open(&mut self, source: &str, tag: &str, node: &Node, extra: &str) {
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.out.push_str(extra);
+        if self.options.source_pos {
+            let (start, end) = node.span;
+            let (l1, c1) = self.position(source, start);
+            let (l2, c2) = self.position(source, end.max(start + 1) - 1);
+            let pos = format!(" data-sourcepos=\"{}:{}-{}:{}\"", l1, c1, l2, c2);
+            self.out.push_str(&pos);
+        }
+        let attributes = self.attributes(source, node.attributes.as_ref(), "");
+        self.out.push_str(&attributes);
+        self.out.push('>');
+    }
+
+    /// Writes the attributes, with `class` before the classes they add.
+    fn attributes(&self, source: &str, attributes: Option<&Attributes>, class: &str) -> String {
+        let text = |(s, e): (usize, usize)| escape(&source[s..e]);
+        let mut classes = vec![];
+        if !class.is_empty() {
+            classes.push(class.to_string());
+        }
+        let mut id = String::new();
+        let mut pairs = String::new();
+        if let Some(attributes) = attributes {
+            if let Some(span) = attributes.id {
+                id = format!(" id=\"{}\"", text(span));
+            }
+            classes.extend(attributes.classes.iter().map(|&c| text(c)));
+            for &(key, value) in &attributes.pairs {
+                let name = &source[key.0..key.1];
+                if name.eq_ignore_ascii_case("class") {
+                    classes.push(text(value));
+                } else if !self.options.safe || is_safe_attribute(name) {
+                    pairs.push_str(&format!(" {}=\"{}\"", text(key), text(value)));
+                }
+            }
+        }
+        match classes.is_empty() {
+            true => id + &pairs,
+            false => format!("{} class=\"{}\"{}", id, classes.join(" "), pairs),
+        }
+    }
+
+    /// One-based line and byte column of `offset`.
+    fn position(&mut self, source: &str, offset: usize) -> (usize, usize) {
+        if self.lines.is_empty() {
+            self.lines = std::iter::once(0)
+                .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+                .collect();
+        }
+        let line = self.lines.partition_point(|&s| s <= offset);
+        (line, offset - self.lines[line - 1] + 1)
+    }
+
+    fn escape(&mut self, s: &str) {
+        self.out.push_str(&escape(s));
+    }
+
+    fn escape_href(&mut self, url: &str) {
+        for c in url.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '\'' => self.out.push_str("&#x27;"),
+                c if c.is_ascii_alphanumeric() || "-_.+!*(),%#@?=;:/$~".contains(c) => {
+                    self.out.push(c)
+                }
+                c => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        self.out.push_str(&format!("%{:02X}", b));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Renderer for Writer<'a> {
+    type Output = String;
+
+    fn finish(&mut self) -> String {
+        self.lines.clear();
+        self.tight.clear();
+        std::mem::take(&mut self.out)
+    }
+
+    fn enter_document(&mut self, source: &str, _node: &Node) {
+        self.out.reserve(source.len() * 3 / 2);
+        self.tight.push(false);
+    }
+
+    fn leave_document(&mut self, _source: &str, _node: &Node) {
+        self.tight.pop();
+    }
+
+    fn enter_block_quote(&mut self, source: &str, node: &Node) {
+        self.container(source, "blockquote", node, "", false);
+    }
+
+    fn leave_block_quote(&mut self, _source: &str, _node: &Node) {
+        self.tight.pop();
+        self.cr();
+        self.out.push_str("</blockquote>\n");
+    }
+
+    fn enter_unordered_list(&mut self, source: &str, node: &Node, loose: bool) {
+        self.container(source, "ul", node, "", !loose);
+    }
+
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.tight.pop();
+        self.out.push_str("</ul>\n");
+    }
+
+    fn enter_ordered_list(&mut self, source: &str, node: &Node, loose: bool) {
+        let start = match list_start(source, node.span.0) {
+            Some(n) if n != 1 => format!(" start=\"{}\"", n),
+            _ => String::new(),
+        };
+        self.container(source, "ol", node, &start, !loose);
+    }
+
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.tight.pop();
+        self.out.push_str("</ol>\n");
+    }
+
+    fn enter_list_item(&mut self, source: &str, node: &Node) {
+        self.cr();
+        self.open(source, "li", node, "");
+    }
+
+    fn leave_list_item(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</li>\n");
+    }
+
+    fn enter_heading(&mut self, source: &str, node: &Node, level: usize) {
+        self.cr();
+        self.open(source, &format!("h{}", level.min(6)), node, "");
+    }
+
+    fn leave_heading(&mut self, _source: &str, _node: &Node, level: usize) {
+        self.out.push_str(&format!("</h{}>\n", level.min(6)));
+    }
+
+    fn enter_paragraph(&mut self, source: &str, node: &Node) {
+        if !self.is_tight() {
+            self.cr();
+            self.open(source, "p", node, "");
+        }
+    }
+
+    fn leave_paragraph(&mut self, _source: &str, _node: &Node) {
+        if !self.is_tight() {
+            self.out.push_str("</p>\n");
+        }
+    }
+
+    fn plaintext(&mut self, source: &str, node: &Node) {
+        self.text(&source[node.span.0..node.span.1]);
+    }
+
+    fn whitespace(&mut self, source: &str, node: &Node) {
+        self.escape(&source[node.span.0..node.span.1]);
+    }
+
+    fn enter_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<del>");
+    }
+
+    fn leave_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</del>");
+    }
+
+    fn math_inline(&mut self, source: &str, node: &Node) {
+        let (start, end) = node.span;
+        self.out.push_str("<span class=\"math inline\">\\(");
+        self.escape(&source[start + 1..end - 1]);
+        self.out.push_str("\\)</span>");
+    }
+
+    fn wiki_link(&mut self, source: &str, node: &Node) {
+        let (embed, target, anchor, label) = match node.kind {
+            Kind::WikiLink {
+                embed,
+                target,
+                anchor,
+                label,
+            } => (embed, target, anchor, label),
+            _ => unreachable!(),
+        };
+        let resolution = self
+            .resolver
+            .and_then(|r| r.resolve(&source[target.0..target.1]));
+        let mut url = match &resolution {
+            Some(r) => r.url.clone(),
+            None => source[target.0..target.1].to_string(),
+        };
+        if let Some((s, e)) = anchor {
+            url = format!("{}#{}", url, &source[s..e]);
+        }
+        if self.options.safe && is_unsafe_url(&url) {
+            url.clear();
+        }
+        let (text_start, text_end) = label.unwrap_or((target.0, anchor.unwrap_or(target).1));
+        let text = &source[text_start..text_end];
+        let class = match resolution {
+            Some(r) if !r.exists => "wiki-link broken",
+            _ => "wiki-link",
+        };
+        let attributes = self.attributes(source, node.attributes.as_ref(), class);
+        match embed {
+            true => {
+                self.out.push_str("<img src=\"");
+                self.escape_href(&url);
+                self.out.push_str("\" alt=\"");
+                self.escape(text);
+                self.out.push_str(&format!("\"{} />", attributes));
+            }
+            false => {
+                self.out.push_str("<a href=\"");
+                self.escape_href(&url);
+                self.out.push_str(&format!("\"{}>", attributes));
+                self.escape(text);
+                self.out.push_str("</a>");
+            }
+        }
+    }
+
+    fn mention(&mut self, source: &str, node: &Node) {
+        self.span("mention", &source[node.span.0..node.span.1]);
+    }
+
+    fn hashtag(&mut self, source: &str, node: &Node) {
+        self.span("hashtag", &source[node.span.0..node.span.1]);
+    }
+
+    fn emoji(&mut self, _source: &str, _node: &Node, emoji: &'static str) {
+        self.out.push_str(emoji);
+    }
+
+    fn enter_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<mark>");
+    }
+
+    fn leave_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</mark>");
+    }
+
+    fn enter_superscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<sup>");
+    }
+
+    fn leave_superscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</sup>");
+    }
+
+    fn enter_subscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<sub>");
+    }
+
+    fn leave_subscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</sub>");
+    }
+
+    fn inline_html(&mut self, source: &str, node: &Node, _html: Html) {
+        let html = &source[node.span.0..node.span.1];
+        match self.options.safe {
+            true => self.escape(html),
+            false => self.out.push_str(html),
+        }
+    }
+
+    fn enter_critic_addition(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<ins>");
+    }
+
+    fn leave_critic_addition(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</ins>");
+    }
+
+    fn enter_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<del>");
+    }
+
+    fn leave_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</del>");
+    }
+
+    fn enter_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("<mark>");
+    }
+
+    fn leave_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("</mark>");
+    }
+
+    fn critic_comment(&mut self, source: &str, node: &Node) {
+        let (start, end) = node.span;
+        self.span("critic-comment", &source[start + 3..end - 3]);
+    }
+
+    fn smart_punctuation(&mut self, _source: &str, _node: &Node, replacement: &'static str) {
+        self.out.push_str(replacement);
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Schemes that can run script when followed, images may use `data:`.
+fn is_unsafe_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    let images = [
+        "data:image/png",
+        "data:image/gif",
+        "data:image/jpeg",
+        "data:image/webp",
+    ];
+    if images.iter().any(|s| url.starts_with(s)) {
+        return false;
+    }
+    ["javascript:", "vbscript:", "file:", "data:"]
+        .iter()
+        .any(|s| url.starts_with(s))
+}
+
+/// Attributes that cannot run script or load anything, the only ones kept
+/// in safe mode.
+fn is_safe_attribute(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key == "id" || key == "class" || key.starts_with("data-")
+}
+
+pub fn render(
+    source: &str,
+    node: &Node,
+    resolver: Option<&dyn Resolver>,
+    options: &Options,
+) -> String {
+    render_with(&mut Writer::new(resolver, options), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::parse;
+    use crate::markdown::render::test::Pages;
+
+    fn html(source: &str, options: &Options) -> String {
+        let parse_options = crate::markdown::Options {
+            math: true,
+            critic_markup: true,
+            ..Default::default()
+        };
+        render(source, &parse(source, &parse_options), None, options)
+    }
+
+    #[test]
+    fn test_blocks() {
+        let options = Options::default();
+        assert_eq!(html("# A & B", &options), "<h1>A &amp; B</h1>\n");
+        assert_eq!(
+            html("a\nb  \nc\n\n> d\n> e", &options),
+            "<p>a\nb<br />\nc</p>\n<blockquote>\n<p>d\ne</p>\n</blockquote>\n"
+        );
+        assert_eq!(
+            html("* a\n  * b\n* c", &options),
+            "<ul>\n<li>a\n<ul>\n<li>b</li>\n</ul>\n</li>\n<li>c</li>\n</ul>\n"
+        );
+        assert_eq!(
+            html("3. a\n\n4. b", &options),
+            "<ol start=\"3\">\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n</ol>\n"
+        );
+        assert_eq!(
+            html("## T {#top .x}", &options),
+            "<h2 id=\"top\" class=\"x\">T</h2>\n"
+        );
+    }
+
+    #[test]
+    fn test_inlines() {
+        let options = Options::default();
+        assert_eq!(
+            html("~~a~~ $x<y$ <b>c</b> {++d++}", &options),
+            "<p><del>a</del> <span class=\"math inline\">\\(x&lt;y\\)</span> \
+             <b>c</b> <ins>d</ins></p>\n"
+        );
+        let safe = Options {
+            safe: true,
+            ..Options::default()
+        };
+        assert_eq!(html("<b>c</b>", &safe), "<p>&lt;b&gt;c&lt;/b&gt;</p>\n");
+    }
+
+    #[test]
+    fn test_safe_attributes() {
+        let safe = Options {
+            safe: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            html("# T {onclick=alert(1) style=x data-n=1}", &safe),
+            "<h1 data-n=\"1\">T</h1>\n"
+        );
+        assert_eq!(
+            html("![[x.png]]{src=javascript:a SRCDOC=b}", &safe),
+            "<p><img src=\"x.png\" alt=\"x.png\" class=\"wiki-link\" /></p>\n"
+        );
+        assert_eq!(
+            html("[[x]]{href=javascript:a formaction=b}", &safe),
+            "<p><a href=\"x\" class=\"wiki-link\">x</a></p>\n"
+        );
+        assert_eq!(
+            html("# T {onclick=a}", &Options::default()),
+            "<h1 onclick=\"a\">T</h1>\n"
+        );
+    }
+
+    #[test]
+    fn test_soft_break() {
+        let source = "a\n b";
+        let space = Options {
+            soft_break: SoftBreak::Space,
+            ..Options::default()
+        };
+        let hard = Options {
+            soft_break: SoftBreak::Break,
+            ..Options::default()
+        };
+        assert_eq!(html(source, &Options::default()), "<p>a\nb</p>\n");
+        assert_eq!(html(source, &space), "<p>a b</p>\n");
+        assert_eq!(html(source, &hard), "<p>a<br />\nb</p>\n");
+    }
+
+    #[test]
+    fn test_source_pos() {
+        let options = Options {
+            source_pos: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            html("# A\n\nb\nc", &options),
+            "<h1 data-sourcepos=\"1:3-1:3\">A</h1>\n<p data-sourcepos=\"3:1-4:1\">b\nc</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_wiki_links() {
+        let source = "[[My Page#Top|see]] [[Missing]] ![[cat.png]]";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render(source, &doc, Some(&Pages), &Options::default()),
+            "<p><a href=\"/wiki/My%20Page#Top\" class=\"wiki-link\">see</a> \
+             <a href=\"/wiki/Missing\" class=\"wiki-link broken\">Missing</a> \
+             <img src=\"/wiki/cat.png\" alt=\"cat.png\" class=\"wiki-link\" /></p>\n"
+        );
+        // Classes from attributes join the link's own
+        let source = "[[Missing]]{.a class=b} ![[x.png]]{#i .c}";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render(source, &doc, Some(&Pages), &Options::default()),
+            "<p><a href=\"/wiki/Missing\" class=\"wiki-link broken a b\">Missing</a> \
+             <img src=\"/wiki/x.png\" alt=\"x.png\" id=\"i\" class=\"wiki-link c\" /></p>\n"
+        );
+    }
+}
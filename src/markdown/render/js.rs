@@ -0,0 +1,188 @@
+use std::convert::TryFrom;
+
+use js_sys::{Array, Object, Reflect};
+use wasm_bindgen::JsValue;
+
+use crate::markdown::render::json::{Json, A, H, K, L, N};
+use crate::markdown::render::{render_with, Renderer, Resolver};
+use crate::markdown::Node;
+
+/// Property names and kind names, created once per render instead of once
+/// per node.
+struct Keys {
+    kind: JsValue,
+    span: JsValue,
+    merkle: JsValue,
+    position: JsValue,
+    children: JsValue,
+    text: JsValue,
+    link: JsValue,
+    html: JsValue,
+    attributes: JsValue,
+    embed: JsValue,
+    target: JsValue,
+    anchor: JsValue,
+    label: JsValue,
+    url: JsValue,
+    exists: JsValue,
+    name: JsValue,
+    self_closing: JsValue,
+    id: JsValue,
+    classes: JsValue,
+    pairs: JsValue,
+    version: JsValue,
+    loose: JsValue,
+    kinds: Vec<JsValue>,
+}
+
+impl Keys {
+    fn new() -> Keys {
+        // Kind names as serialized by `render::json`, indexed by code
+        let kinds = (0..)
+            .map(|code| K::try_from(code + 1))
+            .take_while(Result::is_ok)
+            .map(|k| JsValue::from(serde_json::to_value(k.unwrap()).unwrap().as_str()))
+            .collect();
+        Keys {
+            kind: "kind".into(),
+            span: "span".into(),
+            merkle: "merkle".into(),
+            position: "position".into(),
+            children: "children".into(),
+            text: "text".into(),
+            link: "link".into(),
+            html: "html".into(),
+            attributes: "attributes".into(),
+            embed: "embed".into(),
+            target: "target".into(),
+            anchor: "anchor".into(),
+            label: "label".into(),
+            url: "url".into(),
+            exists: "exists".into(),
+            name: "name".into(),
+            self_closing: "self_closing".into(),
+            id: "id".into(),
+            classes: "classes".into(),
+            pairs: "pairs".into(),
+            version: "version".into(),
+            loose: "loose".into(),
+            kinds,
+        }
+    }
+
+    fn node(&self, n: &N) -> JsValue {
+        let o = Object::new();
+        if let Some(version) = n.version {
+            set(&o, &self.version, &JsValue::from(version));
+        }
+        let kind = &self.kinds[i64::from(n.kind) as usize - 1];
+        set(&o, &self.kind, kind);
+        set(&o, &self.span, &span(n.span));
+        set(&o, &self.merkle, &n.merkle.to_string().into());
+        set(&o, &self.position, &n.position.to_string().into());
+        let children = match &n.children {
+            Some(children) => children
+                .iter()
+                .map(|c| self.node(c))
+                .collect::<Array>()
+                .into(),
+            None => JsValue::NULL,
+        };
+        set(&o, &self.children, &children);
+        set(&o, &self.text, &string(n.text.as_deref()));
+        if let Some(loose) = n.loose {
+            set(&o, &self.loose, &JsValue::from_bool(loose));
+        }
+        if let Some(link) = &n.link {
+            set(&o, &self.link, &self.link(link));
+        }
+        if let Some(html) = &n.html {
+            set(&o, &self.html, &self.html(html));
+        }
+        if let Some(attributes) = &n.attributes {
+            set(&o, &self.attributes, &self.attributes(attributes));
+        }
+        o.into()
+    }
+
+    fn link(&self, link: &L) -> JsValue {
+        let o = Object::new();
+        set(&o, &self.embed, &JsValue::from_bool(link.embed));
+        set(&o, &self.target, &span(link.target));
+        set(&o, &self.anchor, &link.anchor.map_or(JsValue::NULL, span));
+        set(&o, &self.label, &link.label.map_or(JsValue::NULL, span));
+        set(&o, &self.url, &string(link.url.as_deref()));
+        let exists = link.exists.map_or(JsValue::NULL, JsValue::from_bool);
+        set(&o, &self.exists, &exists);
+        o.into()
+    }
+
+    fn html(&self, html: &H) -> JsValue {
+        let o = Object::new();
+        let kind = serde_json::to_value(html.kind).unwrap();
+        set(&o, &self.kind, &kind.as_str().into());
+        set(&o, &self.name, &string(html.name.as_deref()));
+        set(
+            &o,
+            &self.self_closing,
+            &JsValue::from_bool(html.self_closing),
+        );
+        o.into()
+    }
+
+    fn attributes(&self, attributes: &A) -> JsValue {
+        let o = Object::new();
+        set(&o, &self.span, &span(attributes.span));
+        set(&o, &self.id, &string(attributes.id.as_deref()));
+        let classes: Array = attributes.classes.iter().map(|c| string(Some(c))).collect();
+        set(&o, &self.classes, &classes);
+        let pairs: Array = attributes
+            .pairs
+            .iter()
+            .map(|(k, v)| Array::of2(&string(Some(k)), &string(Some(v))))
+            .collect();
+        set(&o, &self.pairs, &pairs);
+        o.into()
+    }
+}
+
+fn set(o: &Object, key: &JsValue, value: &JsValue) {
+    Reflect::set(o, key, value).unwrap();
+}
+
+fn span((start, end): (usize, usize)) -> JsValue {
+    Array::of2(&(start as f64).into(), &(end as f64).into()).into()
+}
+
+fn string(s: Option<&str>) -> JsValue {
+    s.map_or(JsValue::NULL, JsValue::from_str)
+}
+
+/// Builds the tree `render::json` serializes directly as JS objects.
+pub struct Js<'a>(Json<'a>);
+
+impl<'a> Js<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>) -> Self {
+        Js(Json::new(resolver))
+    }
+}
+
+impl<'a> Renderer for Js<'a> {
+    type Output = JsValue;
+
+    fn enter(&mut self, source: &str, node: &Node) {
+        self.0.enter(source, node);
+    }
+
+    fn leave(&mut self, source: &str, node: &Node) {
+        self.0.leave(source, node);
+    }
+
+    fn finish(&mut self) -> JsValue {
+        Keys::new().node(&self.0.tree())
+    }
+}
+
+pub fn render(source: &str, node: &Node, resolver: Option<&dyn Resolver>) -> JsValue {
+    render_with(&mut Js::new(resolver), source, node)
+}
@@ -1,119 +1,604 @@
-use std::convert::Into;
+use std::convert::TryFrom;
+use std::fmt;
 
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use xxhash_rust::xxh64::Xxh64;
 
-use crate::markdown::{Kind, Node};
+use crate::markdown::emoji;
+use crate::markdown::parse::attributes;
+use crate::markdown::render::{render_with, walk, Renderer, Resolver};
+use crate::markdown::{Attributes, Html, Kind, Node};
 
-#[derive(Serialize, Copy, Clone)]
+/// Version of the tree format, written on the root node. Bump it whenever
+/// a field or kind changes meaning.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Node kinds are written by name. The codes feed the merkle hashes and the
+/// binary encoding, so they must never be reused or renumbered.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum K {
     // Container block tokens
-    Document,
-    BlockQuote,
-    Empty,
-    UnorderedList,
-    OrderedList,
-    ListItem,
+    Document = 1,
+    BlockQuote = 2,
+    Empty = 3,
+    UnorderedList = 4,
+    OrderedList = 5,
+    ListItem = 6,
     // Leaf block tokens
-    Heading1,
-    Heading2,
-    Heading3,
-    Heading4,
-    Heading5,
-    Heading6,
-    Paragraph,
-    EmptyLine,
+    Heading1 = 7,
+    Heading2 = 8,
+    Heading3 = 9,
+    Heading4 = 10,
+    Heading5 = 11,
+    Heading6 = 12,
+    Paragraph = 13,
+    EmptyLine = 14,
     // Inline tokens
-    Plaintext,
-    Whitespace,
-}
-
-impl Into<i64> for K {
-    fn into(self) -> i64 {
-        match self {
-            K::Document => 1,
-            K::BlockQuote => 2,
-            K::Empty => 3,
-            K::UnorderedList => 4,
-            K::OrderedList => 5,
-            K::ListItem => 6,
-            K::Heading1 => 7,
-            K::Heading2 => 8,
-            K::Heading3 => 9,
-            K::Heading4 => 10,
-            K::Heading5 => 11,
-            K::Heading6 => 12,
-            K::Paragraph => 13,
-            K::EmptyLine => 14,
-            K::Plaintext => 15,
-            K::Whitespace => 16,
+    Plaintext = 15,
+    Whitespace = 16,
+    Strikethrough = 17,
+    MathInline = 18,
+    WikiLink = 19,
+    Mention = 20,
+    Hashtag = 21,
+    Emoji = 22,
+    Highlight = 23,
+    Superscript = 24,
+    Subscript = 25,
+    InlineHtml = 26,
+    CriticAddition = 27,
+    CriticDeletion = 28,
+    CriticSubstitution = 29,
+    CriticHighlight = 30,
+    CriticComment = 31,
+    SmartPunctuation = 32,
+}
+
+impl From<K> for i64 {
+    fn from(kind: K) -> i64 {
+        kind as i64
+    }
+}
+
+impl TryFrom<i64> for K {
+    type Error = i64;
+
+    fn try_from(code: i64) -> Result<K, i64> {
+        let kinds = [
+            K::Document,
+            K::BlockQuote,
+            K::Empty,
+            K::UnorderedList,
+            K::OrderedList,
+            K::ListItem,
+            K::Heading1,
+            K::Heading2,
+            K::Heading3,
+            K::Heading4,
+            K::Heading5,
+            K::Heading6,
+            K::Paragraph,
+            K::EmptyLine,
+            K::Plaintext,
+            K::Whitespace,
+            K::Strikethrough,
+            K::MathInline,
+            K::WikiLink,
+            K::Mention,
+            K::Hashtag,
+            K::Emoji,
+            K::Highlight,
+            K::Superscript,
+            K::Subscript,
+            K::InlineHtml,
+            K::CriticAddition,
+            K::CriticDeletion,
+            K::CriticSubstitution,
+            K::CriticHighlight,
+            K::CriticComment,
+            K::SmartPunctuation,
+        ];
+        // Codes are one based and in declaration order
+        let kind = usize::try_from(code - 1).ok().and_then(|i| kinds.get(i));
+        kind.copied().ok_or(code)
+    }
+}
+
+/// A 64-bit XXH64 hash. It is written as 16 hex digits since JavaScript
+/// numbers only hold integers up to 2^53 exactly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Hash(pub u64);
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.len() {
+            16 => u64::from_str_radix(&s, 16).ok(),
+            _ => None,
         }
+        .map(Hash)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid hash {:?}", s)))
+    }
+}
+
+#[cfg(test)]
+impl schemars::JsonSchema for Hash {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Hash".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({ "type": "string", "pattern": "^[0-9a-f]{16}$" })
     }
 }
 
-#[derive(Serialize)]
+/// A node of the document tree. Spans are byte offsets into the source.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct N {
+    /// Version of the format, only on the root node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub kind: K,
     pub span: (usize, usize),
-    pub merkle: i64,
+    /// Hash of the kind, text and descendants without their offsets, the
+    /// same wherever the node is as long as its content is
+    pub merkle: Hash,
+    /// Hash of the kind and span, identifies the node by where it is
+    pub position: Hash,
+    /// Child nodes, null for leaves
     pub children: Option<Vec<N>>,
+    /// Text of leaves without their markup, null for containers
     pub text: Option<String>,
+    /// Whether a list has blank lines between its items
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loose: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<L>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<H>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<A>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub enum HtmlK {
+    Open,
+    Close,
+    Comment,
+    ProcessingInstruction,
+    Declaration,
+    Cdata,
+}
+
+/// An inline HTML tag or other markup.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct H {
+    pub kind: HtmlK,
+    /// Lowercase tag name of opening and closing tags
+    pub name: Option<String>,
+    pub self_closing: bool,
+}
+
+/// A wiki link or embed.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct L {
+    pub embed: bool,
+    pub target: (usize, usize),
+    pub anchor: Option<(usize, usize)>,
+    pub label: Option<(usize, usize)>,
+    /// Set when a resolver was given
+    pub url: Option<String>,
+    /// Set when a resolver was given
+    pub exists: Option<bool>,
+}
+
+/// A `{#id .class key=value}` attribute list.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct A {
+    /// Span of the list including its braces
+    pub span: (usize, usize),
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    pub pairs: Vec<(String, String)>,
+}
+
+impl A {
+    fn new(source: &str, attributes: &Attributes) -> A {
+        let text = |(s, e): (usize, usize)| source[s..e].to_string();
+        A {
+            span: attributes.span,
+            id: attributes.id.map(text),
+            classes: attributes.classes.iter().copied().map(text).collect(),
+            pairs: attributes
+                .pairs
+                .iter()
+                .map(|&(k, v)| (text(k), text(v)))
+                .collect(),
+        }
+    }
+}
+
+/// Builds the tree bottom up, each node once its children are built.
+pub struct Json<'a> {
+    resolver: Option<&'a dyn Resolver>,
+    // Children of every node entered but not yet left
+    stack: Vec<Vec<N>>,
+}
+
+impl<'a> Json<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>) -> Self {
+        Json {
+            resolver,
+            stack: vec![vec![]],
+        }
+    }
+
+    /// The tree of the document with the format version on its root.
+    pub(crate) fn tree(&mut self) -> N {
+        let mut n = self.stack.pop().and_then(|mut v| v.pop()).unwrap();
+        n.version = Some(SCHEMA_VERSION);
+        self.stack = vec![vec![]];
+        n
+    }
+}
+
+impl<'a> Renderer for Json<'a> {
+    type Output = String;
+
+    fn enter(&mut self, _source: &str, _node: &Node) {
+        self.stack.push(vec![]);
+    }
+
+    fn leave(&mut self, source: &str, node: &Node) {
+        let children = self.stack.pop().unwrap_or_default();
+        let n = N::new(source, self.resolver, node, children);
+        self.stack.last_mut().unwrap().push(n);
+    }
+
+    fn finish(&mut self) -> String {
+        serde_json::to_string(&self.tree()).unwrap()
+    }
 }
 
 impl N {
-    fn new(source: &str, node: Node) -> N {
+    fn new(source: &str, resolver: Option<&dyn Resolver>, node: &Node, children: Vec<N>) -> N {
+        let loose = match node.kind {
+            Kind::UnorderedList(loose) | Kind::OrderedList(loose) => Some(loose),
+            _ => None,
+        };
+        let mut n = N::new_kind(source, resolver, node, children);
+        n.loose = loose;
+        if let Some(a) = &node.attributes {
+            // Changing only the attributes must still change the hash
+            let (start, end) = a.span;
+            n.merkle = Hasher::new(n.kind)
+                .u64(n.merkle.0)
+                .str(&source[start..end])
+                .finish();
+            n.attributes = Some(A::new(source, a));
+        }
+        n
+    }
+
+    fn new_kind(source: &str, resolver: Option<&dyn Resolver>, node: &Node, children: Vec<N>) -> N {
         match node.kind {
-            Kind::Document => render_container(K::Document, source, node),
-            Kind::BlockQuote => render_container(K::BlockQuote, source, node),
-            Kind::Empty => render_container(K::Empty, source, node),
-            Kind::UnorderedList(..) => render_container(K::UnorderedList, source, node),
-            Kind::OrderedList(..) => render_container(K::OrderedList, source, node),
-            Kind::ListItem => render_container(K::ListItem, source, node),
-            Kind::Heading(size) => render_heading(source, node, size),
-            Kind::Paragraph => render_container(K::Paragraph, source, node),
+            Kind::Document => render_container(K::Document, node, children),
+            Kind::BlockQuote => render_container(K::BlockQuote, node, children),
+            Kind::Empty => render_container(K::Empty, node, children),
+            Kind::UnorderedList(..) => render_container(K::UnorderedList, node, children),
+            Kind::OrderedList(..) => render_container(K::OrderedList, node, children),
+            Kind::ListItem => render_container(K::ListItem, node, children),
+            Kind::Heading(size) => render_heading(node, children, size),
+            Kind::Paragraph => render_container(K::Paragraph, node, children),
             Kind::EmptyLine => render_inline(K::EmptyLine, source, node),
             Kind::Plaintext => render_inline(K::Plaintext, source, node),
             Kind::Whitespace => render_inline(K::Whitespace, source, node),
+            Kind::Strikethrough => render_container(K::Strikethrough, node, children),
+            Kind::MathInline => render_math(K::MathInline, source, node),
+            Kind::WikiLink { .. } => render_wiki_link(K::WikiLink, source, resolver, node),
+            Kind::Mention => render_inline(K::Mention, source, node),
+            Kind::Hashtag => render_inline(K::Hashtag, source, node),
+            Kind::Emoji(emoji) => render_replaced(K::Emoji, source, node, emoji),
+            Kind::Highlight => render_container(K::Highlight, node, children),
+            Kind::Superscript => render_container(K::Superscript, node, children),
+            Kind::Subscript => render_container(K::Subscript, node, children),
+            Kind::InlineHtml(html) => render_html(K::InlineHtml, source, node, html),
+            Kind::CriticAddition => render_container(K::CriticAddition, node, children),
+            Kind::CriticDeletion => render_container(K::CriticDeletion, node, children),
+            Kind::CriticSubstitution => render_container(K::CriticSubstitution, node, children),
+            Kind::CriticHighlight => render_container(K::CriticHighlight, node, children),
+            Kind::CriticComment => render_comment(K::CriticComment, source, node),
+            Kind::SmartPunctuation(s) => render_replaced(K::SmartPunctuation, source, node, s),
+        }
+    }
+}
+
+impl N {
+    /// Converts back into the parsed tree. The source is needed for what the
+    /// format only keeps as text, such as attribute lists and emoji
+    /// shortcodes. Returns `None` when the tree does not fit the source.
+    pub fn into_node(self, source: &str) -> Option<Node> {
+        let (start, end) = self.span;
+        let loose = self.loose.unwrap_or(false);
+        let kind = match self.kind {
+            K::Document => Kind::Document,
+            K::BlockQuote => Kind::BlockQuote,
+            K::Empty => Kind::Empty,
+            K::UnorderedList => Kind::UnorderedList(loose),
+            K::OrderedList => Kind::OrderedList(loose),
+            K::ListItem => Kind::ListItem,
+            K::Heading1 => Kind::Heading(1),
+            K::Heading2 => Kind::Heading(2),
+            K::Heading3 => Kind::Heading(3),
+            K::Heading4 => Kind::Heading(4),
+            K::Heading5 => Kind::Heading(5),
+            K::Heading6 => Kind::Heading(6),
+            K::Paragraph => Kind::Paragraph,
+            K::EmptyLine => Kind::EmptyLine,
+            K::Plaintext => Kind::Plaintext,
+            K::Whitespace => Kind::Whitespace,
+            K::Strikethrough => Kind::Strikethrough,
+            K::MathInline => Kind::MathInline,
+            K::WikiLink => {
+                let link = self.link.as_ref()?;
+                Kind::WikiLink {
+                    embed: link.embed,
+                    target: link.target,
+                    anchor: link.anchor,
+                    label: link.label,
+                }
+            }
+            K::Mention => Kind::Mention,
+            K::Hashtag => Kind::Hashtag,
+            // The span covers the shortcode and its colons
+            K::Emoji => Kind::Emoji(emoji::lookup(source.get(start + 1..end.checked_sub(1)?)?)?),
+            K::Highlight => Kind::Highlight,
+            K::Superscript => Kind::Superscript,
+            K::Subscript => Kind::Subscript,
+            K::InlineHtml => Kind::InlineHtml(self.html.as_ref()?.to_html(start)),
+            K::CriticAddition => Kind::CriticAddition,
+            K::CriticDeletion => Kind::CriticDeletion,
+            K::CriticSubstitution => Kind::CriticSubstitution,
+            K::CriticHighlight => Kind::CriticHighlight,
+            K::CriticComment => Kind::CriticComment,
+            K::SmartPunctuation => {
+                let text = self.text.as_deref()?;
+                Kind::SmartPunctuation(SMART_PUNCTUATION.iter().find(|&&s| s == text)?)
+            }
+        };
+        let attributes = match self.attributes {
+            // Parsed from inside the braces
+            Some(a) => {
+                let (start, end) = a.span;
+                Some(attributes::parse(source, start + 1, end.checked_sub(1)?)?)
+            }
+            None => None,
+        };
+        let children = self
+            .children
+            .unwrap_or_default()
+            .into_iter()
+            .map(|n| n.into_node(source))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Node {
+            kind,
+            span: self.span,
+            children,
+            attributes,
+        })
+    }
+}
+
+impl H {
+    /// The tag name follows `<` or `</` at the start of the node.
+    fn to_html(&self, start: usize) -> Html {
+        let name = |offset: usize| {
+            let len = self.name.as_ref().map_or(0, |n| n.len());
+            (start + offset, start + offset + len)
+        };
+        match self.kind {
+            HtmlK::Open => Html::Open {
+                name: name(1),
+                self_closing: self.self_closing,
+            },
+            HtmlK::Close => Html::Close { name: name(2) },
+            HtmlK::Comment => Html::Comment,
+            HtmlK::ProcessingInstruction => Html::ProcessingInstruction,
+            HtmlK::Declaration => Html::Declaration,
+            HtmlK::Cdata => Html::Cdata,
         }
     }
 }
 
-fn render_container(kind: K, source: &str, node: Node) -> N {
+/// Every replacement smart punctuation makes.
+const SMART_PUNCTUATION: [&str; 7] = [
+    "\u{2013}", "\u{2014}", "\u{2026}", "\u{2018}", "\u{2019}", "\u{201c}", "\u{201d}",
+];
+
+fn render_container(kind: K, node: &Node, children: Vec<N>) -> N {
     let (start, end) = node.span;
-    let children = render_children(source, node);
     N {
+        version: None,
         kind,
         span: (start, end),
-        merkle: hash_n(kind, (start, end), &children, &None),
-        children,
+        merkle: hash_children(kind, &children),
+        position: hash_position(kind, (start, end)),
+        children: Some(children),
         text: None,
+        link: None,
+        html: None,
+        loose: None,
+        attributes: None,
     }
 }
 
-fn render_inline(kind: K, source: &str, node: Node) -> N {
+fn render_inline(kind: K, source: &str, node: &Node) -> N {
     let (start, end) = node.span;
     let text = &source[start..end];
     N {
+        version: None,
         kind,
         span: (start, end),
-        merkle: hash_str(text),
+        merkle: hash_text(kind, text),
+        position: hash_position(kind, (start, end)),
         children: None,
         text: Some(text.into()),
+        link: None,
+        html: None,
+        loose: None,
+        attributes: None,
     }
 }
 
-fn render_children(source: &str, node: Node) -> Option<Vec<N>> {
-    match node.kind {
-        Kind::EmptyLine | Kind::Plaintext | Kind::Whitespace => None,
-        _ => Some(
-            node.children
-                .into_iter()
-                .map(|n| N::new(source, n))
-                .collect(),
-        ),
+fn render_math(kind: K, source: &str, node: &Node) -> N {
+    let (start, end) = node.span;
+    // Strip the delimiters so consumers receive the TeX source only
+    let text = &source[start + 1..end - 1];
+    N {
+        version: None,
+        kind,
+        span: (start, end),
+        merkle: hash_text(kind, text),
+        position: hash_position(kind, (start, end)),
+        children: None,
+        text: Some(text.into()),
+        link: None,
+        html: None,
+        loose: None,
+        attributes: None,
     }
 }
 
-fn render_heading(source: &str, node: Node, size: usize) -> N {
+fn render_comment(kind: K, source: &str, node: &Node) -> N {
+    let (start, end) = node.span;
+    // Strip the "{>>" and "<<}" markers
+    let text = &source[start + 3..end - 3];
+    N {
+        version: None,
+        kind,
+        span: (start, end),
+        merkle: hash_text(kind, text),
+        position: hash_position(kind, (start, end)),
+        children: None,
+        text: Some(text.into()),
+        link: None,
+        html: None,
+        loose: None,
+        attributes: None,
+    }
+}
+
+fn render_html(kind: K, source: &str, node: &Node, html: Html) -> N {
+    let (start, end) = node.span;
+    let text = &source[start..end];
+    let (kind_h, name, self_closing) = match html {
+        Html::Open { name, self_closing } => (HtmlK::Open, Some(name), self_closing),
+        Html::Close { name } => (HtmlK::Close, Some(name), false),
+        Html::Comment => (HtmlK::Comment, None, false),
+        Html::ProcessingInstruction => (HtmlK::ProcessingInstruction, None, false),
+        Html::Declaration => (HtmlK::Declaration, None, false),
+        Html::Cdata => (HtmlK::Cdata, None, false),
+    };
+    N {
+        version: None,
+        kind,
+        span: (start, end),
+        merkle: hash_text(kind, text),
+        position: hash_position(kind, (start, end)),
+        children: None,
+        text: Some(text.into()),
+        link: None,
+        html: Some(H {
+            kind: kind_h,
+            name: name.map(|(s, e)| source[s..e].to_ascii_lowercase()),
+            self_closing,
+        }),
+        loose: None,
+        attributes: None,
+    }
+}
+
+/// Leaves whose text differs from the source they were written as.
+fn render_replaced(kind: K, source: &str, node: &Node, text: &str) -> N {
+    let (start, end) = node.span;
+    N {
+        version: None,
+        kind,
+        span: (start, end),
+        merkle: hash_text(kind, &source[start..end]),
+        position: hash_position(kind, (start, end)),
+        children: None,
+        text: Some(text.into()),
+        link: None,
+        html: None,
+        loose: None,
+        attributes: None,
+    }
+}
+
+fn render_wiki_link(kind: K, source: &str, resolver: Option<&dyn Resolver>, node: &Node) -> N {
+    let (start, end) = node.span;
+    let (embed, target, anchor, label) = match node.kind {
+        Kind::WikiLink {
+            embed,
+            target,
+            anchor,
+            label,
+        } => (embed, target, anchor, label),
+        _ => unreachable!(),
+    };
+    // Show the label when there is one, otherwise the link itself
+    let (text_start, text_end) = label.unwrap_or((target.0, anchor.unwrap_or(target).1));
+    let text = &source[text_start..text_end];
+    let resolution = resolver.and_then(|r| r.resolve(&source[target.0..target.1]));
+    // A target that starts or stops resolving changes how the link is shown
+    let mut merkle = Hasher::new(kind);
+    merkle.str(&source[start..end]);
+    if let Some(r) = &resolution {
+        merkle.str(&r.url).u64(r.exists as u64);
+    }
+    let merkle = merkle.finish();
+    N {
+        version: None,
+        kind,
+        span: (start, end),
+        merkle,
+        position: hash_position(kind, (start, end)),
+        children: None,
+        text: Some(text.into()),
+        link: Some(L {
+            embed,
+            target,
+            anchor,
+            label,
+            url: resolution.as_ref().map(|r| r.url.clone()),
+            exists: resolution.map(|r| r.exists),
+        }),
+        html: None,
+        loose: None,
+        attributes: None,
+    }
+}
+
+fn render_heading(node: &Node, children: Vec<N>, size: usize) -> N {
     let kind = match size {
         1 => K::Heading1,
         2 => K::Heading2,
@@ -122,37 +607,293 @@ fn render_heading(source: &str, node: Node, size: usize) -> N {
         5 => K::Heading5,
         _ => K::Heading6,
     };
-    render_container(kind, source, node)
+    render_container(kind, node, children)
 }
 
-pub fn render(source: &str, node: Node) -> String {
-    let n = N::new(source, node);
-    serde_json::to_string(&n).unwrap()
+/// The tree of the document with the format version on its root.
+pub(crate) fn tree(source: &str, node: &Node, resolver: Option<&dyn Resolver>) -> N {
+    let mut json = Json::new(resolver);
+    walk(&mut json, source, node);
+    json.tree()
 }
 
-fn hash_n(kind: K, span: (usize, usize), children: &Option<Vec<N>>, text: &Option<String>) -> i64 {
-    let hash: i64 = match (children, text) {
-        (Some(v), None) => hash_vec(v),
-        (None, Some(s)) => hash_str(&s[..]),
-        _ => 0,
-    };
-    let (start, end) = span;
-    let start = start as i64;
-    let end = end as i64;
-    let kind: i64 = kind.into();
-    start + 11 * end + 17 * hash + 31 * kind
+pub fn render(source: &str, node: &Node, resolver: Option<&dyn Resolver>) -> String {
+    render_with(&mut Json::new(resolver), source, node)
+}
+
+/// Feeds the parts of a node to XXH64. Strings are prefixed with their
+/// length so that splitting the same bytes differently changes the hash.
+struct Hasher(Xxh64);
+
+impl Hasher {
+    fn new(kind: K) -> Hasher {
+        let mut hasher = Hasher(Xxh64::new(0));
+        hasher.u64(i64::from(kind) as u64);
+        hasher
+    }
+
+    fn u64(&mut self, n: u64) -> &mut Hasher {
+        self.0.update(&n.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, s: &str) -> &mut Hasher {
+        self.u64(s.len() as u64);
+        self.0.update(s.as_bytes());
+        self
+    }
+
+    fn finish(&self) -> Hash {
+        Hash(self.0.digest())
+    }
+}
+
+fn hash_text(kind: K, text: &str) -> Hash {
+    Hasher::new(kind).str(text).finish()
+}
+
+fn hash_children(kind: K, children: &[N]) -> Hash {
+    let mut hasher = Hasher::new(kind);
+    hasher.u64(children.len() as u64);
+    for n in children {
+        hasher.u64(n.merkle.0);
+    }
+    hasher.finish()
 }
 
-fn hash_str(s: &str) -> i64 {
-    s.chars().into_iter().fold(0, |hash, c| {
-        let h = (hash << 5) - hash + (c as i64);
-        h | 0
-    })
+fn hash_position(kind: K, (start, end): (usize, usize)) -> Hash {
+    Hasher::new(kind).u64(start as u64).u64(end as u64).finish()
 }
 
-fn hash_vec(v: &Vec<N>) -> i64 {
-    v.iter().fold(0, |hash, n| {
-        let h = (hash << 5) - hash + n.merkle;
-        h | 0
-    })
+#[cfg(test)]
+mod test {
+    extern crate test;
+
+    use super::*;
+    use crate::markdown::parse::test::{large, options};
+    use crate::markdown::render::test::{Pages, SOURCE};
+    use crate::markdown::{parse, Options};
+    use std::collections::HashSet;
+    use test::Bencher;
+
+    #[test]
+    fn test_wiki_link_resolution() {
+        let source = "[[Home]] [[Missing|gone]]";
+        let doc = parse(source, &Options::default());
+        let n = tree(source, &doc, Some(&Pages));
+        let p = &n.children.unwrap()[0];
+        let links = p.children.as_ref().unwrap();
+
+        let home = links[0].link.as_ref().unwrap();
+        assert_eq!(home.url.as_deref(), Some("/wiki/Home"));
+        assert_eq!(home.exists, Some(true));
+        assert_eq!(links[0].text.as_deref(), Some("Home"));
+
+        let missing = links[2].link.as_ref().unwrap();
+        assert_eq!(missing.url.as_deref(), Some("/wiki/Missing"));
+        assert_eq!(missing.exists, Some(false));
+        assert_eq!(links[2].text.as_deref(), Some("gone"));
+    }
+
+    #[test]
+    fn test_kind_codes() {
+        for code in 1..=32 {
+            let kind = K::try_from(code).unwrap();
+            assert_eq!(i64::from(kind), code);
+        }
+        assert!(K::try_from(0).is_err());
+        assert!(K::try_from(33).is_err());
+    }
+
+    #[test]
+    fn test_attributes() {
+        let source = "# Intro {#start .big lang=\"en gb\"}";
+        let n = tree(source, &parse(source, &Options::default()), None);
+        let h = &n.children.unwrap()[0];
+        let a = h.attributes.as_ref().unwrap();
+        assert_eq!(a.id.as_deref(), Some("start"));
+        assert_eq!(a.classes, vec!["big"]);
+        assert_eq!(a.pairs, vec![("lang".to_string(), "en gb".to_string())]);
+
+        let plain = tree("# Intro", &parse("# Intro", &Options::default()), None);
+        assert_ne!(plain.children.unwrap()[0].merkle, h.merkle);
+    }
+
+    #[test]
+    fn test_merkle_ignores_offsets() {
+        let old = "# Title\n\nSome *text* and [[Home]]";
+        let new = "A paragraph\n\nabove\n\n# Title\n\nSome *text* and [[Home]]";
+        let old = tree(old, &parse(old, &Options::default()), Some(&Pages));
+        let new = tree(new, &parse(new, &Options::default()), Some(&Pages));
+        let (old, new) = (old.children.unwrap(), new.children.unwrap());
+        for (a, b) in old.iter().rev().zip(new.iter().rev()).take(3) {
+            assert_eq!(a.merkle, b.merkle);
+            assert_ne!(a.position, b.position);
+        }
+
+        // Same text in another kind of node
+        let a = tree("*a*", &parse("*a*", &Options::default()), None);
+        let b = tree("**a**", &parse("**a**", &Options::default()), None);
+        assert_ne!(a.merkle, b.merkle);
+    }
+
+    #[test]
+    fn test_hash_overflow() {
+        // Debug builds panic on overflowing arithmetic
+        let long = "x".repeat(1 << 16);
+        assert_ne!(hash_text(K::Plaintext, &long), hash_text(K::Plaintext, ""));
+        assert_ne!(
+            hash_position(K::Document, (usize::MAX, usize::MAX)),
+            hash_position(K::Document, (0, 0))
+        );
+        let source = "> ".repeat(50) + &"word ".repeat(5000);
+        tree(&source, &parse(&source, &Options::default()), None);
+    }
+
+    #[test]
+    fn test_hash_precision() {
+        for &n in &[0, 1, (1 << 53) + 1, u64::MAX] {
+            let json = serde_json::to_string(&Hash(n)).unwrap();
+            assert_eq!(json.len(), 18, "{}", json);
+            assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), Hash(n));
+        }
+        assert_eq!(
+            serde_json::to_string(&Hash(u64::MAX)).unwrap(),
+            "\"ffffffffffffffff\""
+        );
+        assert!(serde_json::from_str::<Hash>("\"ff\"").is_err());
+        assert!(serde_json::from_str::<Hash>("255").is_err());
+
+        let source = "Some text";
+        let json = render(source, &parse(source, &Options::default()), None);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["merkle"].is_string());
+        assert!(value["position"].is_string());
+    }
+
+    #[test]
+    fn test_hash_collisions() {
+        let mut seen = HashSet::new();
+        // Every string of up to three of these characters, which include
+        // pairs the old 31 multiplier hash mapped together, like "Aa" and "BB"
+        let alphabet = ["", "A", "B", "a", "b", "\u{e9}", " "];
+        for a in &alphabet {
+            for b in &alphabet {
+                for c in &alphabet {
+                    let text = format!("{}{}{}", a, b, c);
+                    seen.insert((text.clone(), hash_text(K::Plaintext, &text)));
+                }
+            }
+        }
+        let texts: HashSet<_> = seen.iter().map(|(t, _)| t).collect();
+        let hashes: HashSet<_> = seen.iter().map(|(_, h)| h).collect();
+        assert_eq!(texts.len(), hashes.len());
+
+        // Kind, order and grouping of children all count
+        let merkle = |source: &str| tree(source, &parse(source, &Options::default()), None).merkle;
+        let sources = [
+            "a",
+            "~~a~~",
+            "# a",
+            "## a",
+            "> a",
+            "* a",
+            "1. a",
+            "a ~~b~~",
+            "~~b~~ a",
+            "~~ab~~",
+            "~~a~~~~b~~",
+            "a\n\nb",
+            "b\n\na",
+            "* a\n* b",
+            "* a\n\n* b",
+        ];
+        let merkles: HashSet<_> = sources.iter().map(|s| merkle(s)).collect();
+        assert_eq!(merkles.len(), sources.len());
+    }
+
+    fn schema() -> String {
+        let schema = schemars::schema_for!(N);
+        serde_json::to_string_pretty(&schema).unwrap() + "\n"
+    }
+
+    #[test]
+    fn test_schema_published() {
+        // Run with UPDATE_SCHEMA=1 to write the schema after changing N
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/tree.schema.json");
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, schema()).unwrap();
+        }
+        let published = std::fs::read_to_string(path).unwrap();
+        assert!(
+            published == schema(),
+            "schema/tree.schema.json is out of date"
+        );
+    }
+
+    #[test]
+    fn test_schema_validates() {
+        let schema: serde_json::Value = serde_json::from_str(&schema()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        for source in [SOURCE, "", "1. a\n2. b"] {
+            let json = render(source, &parse(source, &options()), Some(&Pages));
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert!(validator.is_valid(&value), "{}", json);
+            assert_eq!(value["version"], SCHEMA_VERSION);
+        }
+        let mut invalid: serde_json::Value =
+            serde_json::from_str(&render("a", &parse("a", &options()), None)).unwrap();
+        invalid["kind"] = "Heading7".into();
+        assert!(!validator.is_valid(&invalid));
+    }
+
+    #[test]
+    fn test_into_node() {
+        for source in [
+            SOURCE,
+            "",
+            "* a\n\n  b\n* c",
+            "<br/> </B> <?x?> <!X> <![CDATA[y]]>",
+        ] {
+            let json = render(source, &parse(source, &options()), Some(&Pages));
+            let n: N = serde_json::from_str(&json).unwrap();
+            assert_eq!(n.version, Some(SCHEMA_VERSION));
+            assert_eq!(
+                n.into_node(source),
+                Some(parse(source, &options())),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_into_node_mismatch() {
+        let json = render(":tada:", &parse(":tada:", &options()), None);
+        let n: N = serde_json::from_str(&json).unwrap();
+        assert_eq!(n.into_node(":nope:"), None);
+    }
+
+    #[bench]
+    fn bench_large_json(b: &mut Bencher) {
+        let source = large();
+        let options = Options {
+            math: true,
+            ..Options::default()
+        };
+        let doc = parse(&source, &options);
+        b.iter(|| render(&source, &doc, None));
+    }
+
+    #[bench]
+    fn bench_large_tree(b: &mut Bencher) {
+        // The part of the JSON path that `render::js` shares
+        let source = large();
+        let options = Options {
+            math: true,
+            ..Options::default()
+        };
+        let doc = parse(&source, &options);
+        b.iter(|| tree(&source, &doc, None));
+    }
 }
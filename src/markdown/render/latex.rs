@@ -0,0 +1,521 @@
+use crate::markdown::render::{
+    continuation, is_blank, list_start, render_with, Renderer, Resolver,
+};
+use crate::markdown::{Kind, Node};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Map level one headings to `\chapter` instead of `\section`
+    pub chapters: bool,
+    /// Wrap the body in a complete document with the packages it needs
+    pub standalone: bool,
+}
+
+/// Sectioning commands from the top, the last one only for level six
+/// headings when they start at `\section`.
+const SECTIONS: [&str; 7] = [
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+    "subparagraph*",
+];
+
+const COUNTERS: [&str; 4] = ["enumi", "enumii", "enumiii", "enumiv"];
+
+const PREAMBLE: &str = "\\documentclass{article}
+\\usepackage[normalem]{ulem}
+\\usepackage{soul}
+\\usepackage{graphicx}
+\\usepackage{hyperref}
+
+\\begin{document}
+
+";
+
+pub struct Writer<'a> {
+    resolver: Option<&'a dyn Resolver>,
+    options: &'a Options,
+    // Blocks in every open container, and whether they are tight
+    blocks: Vec<(usize, bool)>,
+    // Number of enclosing enumerate environments
+    enumerate: usize,
+    out: String,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(resolver: Option<&'a dyn Resolver>, options: &'a Options) -> Self {
+        Writer {
+            resolver,
+            options,
+            blocks: vec![],
+            enumerate: 0,
+            out: String::new(),
+        }
+    }
+
+    /// Separates a block from the one before it by a blank line unless the
+    /// container is tight.
+    fn separate(&mut self) {
+        if let Some((count, tight)) = self.blocks.last_mut() {
+            if *count > 0 && !*tight {
+                self.out.push('\n');
+            }
+            *count += 1;
+        }
+    }
+
+    fn begin(&mut self, name: &str, head: &str, tight: bool) {
+        self.separate();
+        self.out.push_str(&format!("\\begin{{{}}}\n{}", name, head));
+        self.blocks.push((0, tight));
+    }
+
+    fn end(&mut self, name: &str) {
+        self.blocks.pop();
+        self.out.push_str(&format!("\\end{{{}}}\n", name));
+    }
+
+    /// Writes text, turning two or more trailing spaces into a line break.
+    fn text(&mut self, text: &str) {
+        let mut lines = text.split('\n').enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let line = match i {
+                0 => line,
+                _ => continuation(line),
+            };
+            if lines.peek().is_none() {
+                self.escape(line);
+                break;
+            }
+            let trimmed = line.trim_end_matches(' ');
+            self.escape(trimmed);
+            match line.len() - trimmed.len() {
+                n if n >= 2 => self.out.push_str("\\\\\n"),
+                _ => self.out.push('\n'),
+            }
+        }
+    }
+
+    fn escape(&mut self, s: &str) {
+        // `\item` and `\\` would read a `[` right after them as an optional
+        // argument, and `\\` a `*` as its star
+        let after_item = self.out.ends_with("\\item ");
+        let after_break = self.out.ends_with("\\\\\n");
+        match s.chars().next() {
+            Some('[') if after_item || after_break => self.out.push_str("{}"),
+            Some('*') if after_break => self.out.push_str("{}"),
+            _ => {}
+        }
+        self.out.push_str(&escape(s));
+    }
+}
+
+impl<'a> Renderer for Writer<'a> {
+    type Output = String;
+
+    fn finish(&mut self) -> String {
+        self.blocks.clear();
+        self.enumerate = 0;
+        std::mem::take(&mut self.out)
+    }
+
+    fn enter_document(&mut self, source: &str, _node: &Node) {
+        self.out.reserve(source.len() * 3 / 2);
+        if self.options.standalone {
+            self.out.push_str(PREAMBLE);
+        }
+        self.blocks.push((0, false));
+    }
+
+    fn leave_document(&mut self, _source: &str, _node: &Node) {
+        self.blocks.pop();
+        if self.options.standalone {
+            self.out.push_str("\n\\end{document}\n");
+        }
+    }
+
+    fn enter_block_quote(&mut self, _source: &str, _node: &Node) {
+        self.begin("quote", "", false);
+    }
+
+    fn leave_block_quote(&mut self, _source: &str, _node: &Node) {
+        self.end("quote");
+    }
+
+    fn enter_unordered_list(&mut self, _source: &str, _node: &Node, loose: bool) {
+        self.begin("itemize", "", !loose);
+    }
+
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.end("itemize");
+    }
+
+    fn enter_ordered_list(&mut self, source: &str, node: &Node, loose: bool) {
+        // Items are numbered after the counter, which starts at zero
+        let start = list_start(source, node.span.0).unwrap_or(1);
+        let counter = match COUNTERS.get(self.enumerate) {
+            Some(c) if start != 1 => {
+                format!("\\setcounter{{{}}}{{{}}}\n", c, start.saturating_sub(1))
+            }
+            _ => String::new(),
+        };
+        self.enumerate += 1;
+        self.begin("enumerate", &counter, !loose);
+    }
+
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.end("enumerate");
+        self.enumerate -= 1;
+    }
+
+    fn enter_list_item(&mut self, _source: &str, node: &Node) {
+        self.out.push_str("\\item");
+        // Nested blocks other than text start on a line of their own
+        match node.children.iter().find(|n| !is_blank(n)) {
+            Some(n) if n.kind == Kind::Paragraph => self.out.push(' '),
+            _ => self.out.push('\n'),
+        }
+        let tight = self.blocks.last().is_some_and(|&(_, tight)| tight);
+        self.blocks.push((0, tight));
+    }
+
+    fn leave_list_item(&mut self, _source: &str, _node: &Node) {
+        self.blocks.pop();
+    }
+
+    fn enter_heading(&mut self, _source: &str, _node: &Node, level: usize) {
+        self.separate();
+        let command = SECTIONS[level.clamp(1, 6) - self.options.chapters as usize];
+        self.out.push_str(&format!("\\{}{{", command));
+    }
+
+    fn leave_heading(&mut self, source: &str, node: &Node, _level: usize) {
+        self.out.push('}');
+        if let Some((s, e)) = node.attributes.as_ref().and_then(|a| a.id) {
+            self.out
+                .push_str(&format!("\\label{{{}}}", label(&source[s..e])));
+        }
+        self.out.push('\n');
+    }
+
+    fn enter_paragraph(&mut self, _source: &str, _node: &Node) {
+        self.separate();
+    }
+
+    fn leave_paragraph(&mut self, _source: &str, _node: &Node) {
+        self.out.push('\n');
+    }
+
+    fn plaintext(&mut self, source: &str, node: &Node) {
+        self.text(&source[node.span.0..node.span.1]);
+    }
+
+    fn whitespace(&mut self, source: &str, node: &Node) {
+        self.escape(&source[node.span.0..node.span.1]);
+    }
+
+    fn enter_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\sout{");
+    }
+
+    fn leave_strikethrough(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn math_inline(&mut self, source: &str, node: &Node) {
+        self.out.push_str(&source[node.span.0..node.span.1]);
+    }
+
+    fn wiki_link(&mut self, source: &str, node: &Node) {
+        let (embed, target, anchor, label) = match node.kind {
+            Kind::WikiLink {
+                embed,
+                target,
+                anchor,
+                label,
+            } => (embed, target, anchor, label),
+            _ => unreachable!(),
+        };
+        let mut url = self
+            .resolver
+            .and_then(|r| r.resolve(&source[target.0..target.1]))
+            .map(|r| r.url)
+            .unwrap_or_else(|| source[target.0..target.1].to_string());
+        let (text_start, text_end) = label.unwrap_or((target.0, anchor.unwrap_or(target).1));
+        match embed {
+            true => self
+                .out
+                .push_str(&format!("\\includegraphics{{{}}}", path(&url))),
+            false => {
+                if let Some((s, e)) = anchor {
+                    url = format!("{}#{}", url, &source[s..e]);
+                }
+                self.out
+                    .push_str(&format!("\\href{{{}}}{{", escape_url(&url)));
+                self.escape(&source[text_start..text_end]);
+                self.out.push('}');
+            }
+        }
+    }
+
+    fn mention(&mut self, source: &str, node: &Node) {
+        self.escape(&source[node.span.0..node.span.1]);
+    }
+
+    fn hashtag(&mut self, source: &str, node: &Node) {
+        self.escape(&source[node.span.0..node.span.1]);
+    }
+
+    fn emoji(&mut self, _source: &str, _node: &Node, emoji: &'static str) {
+        self.out.push_str(emoji);
+    }
+
+    fn enter_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\hl{");
+    }
+
+    fn leave_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn enter_superscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\textsuperscript{");
+    }
+
+    fn leave_superscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn enter_subscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\textsubscript{");
+    }
+
+    fn leave_subscript(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    // Raw HTML has no meaning here, so inline_html writes nothing
+
+    fn enter_critic_addition(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\uline{");
+    }
+
+    fn leave_critic_addition(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn enter_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\sout{");
+    }
+
+    fn leave_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn enter_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push_str("\\hl{");
+    }
+
+    fn leave_critic_highlight(&mut self, _source: &str, _node: &Node) {
+        self.out.push('}');
+    }
+
+    fn critic_comment(&mut self, source: &str, node: &Node) {
+        let (start, end) = node.span;
+        self.out.push_str("\\marginpar{");
+        self.escape(&source[start + 3..end - 3]);
+        self.out.push('}');
+    }
+
+    fn smart_punctuation(&mut self, _source: &str, _node: &Node, replacement: &'static str) {
+        self.out.push_str(replacement);
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '~' => result.push_str("\\textasciitilde{}"),
+            '^' => result.push_str("\\textasciicircum{}"),
+            '\\' => result.push_str("\\textbackslash{}"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// URLs are read verbatim by hyperref except for these.
+fn escape_url(url: &str) -> String {
+    let mut result = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '%' | '#' | '\\' | '{' | '}' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// graphicx reads file names as they are, so characters that would start a
+/// comment or end the argument are left out.
+fn path(path: &str) -> String {
+    path.chars().filter(|c| !"%#\\{}".contains(*c)).collect()
+}
+
+/// Keeps label names to characters every package accepts in them.
+fn label(id: &str) -> String {
+    id.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "-:.".contains(c) {
+            true => c,
+            false => '-',
+        })
+        .collect()
+}
+
+/// Writes the document as LaTeX, math is copied through unchanged.
+pub fn render(
+    source: &str,
+    node: &Node,
+    resolver: Option<&dyn Resolver>,
+    options: &Options,
+) -> String {
+    render_with(&mut Writer::new(resolver, options), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::parse;
+    use crate::markdown::render::test::Pages;
+
+    fn latex(source: &str, options: &Options) -> String {
+        let parse_options = crate::markdown::Options {
+            math: true,
+            superscript: true,
+            critic_markup: true,
+            ..Default::default()
+        };
+        render(source, &parse(source, &parse_options), None, options)
+    }
+
+    #[test]
+    fn test_blocks() {
+        let options = Options::default();
+        assert_eq!(
+            latex("# Intro {#intro}\n\n## A\n\n#### B\n\nText", &options),
+            "\\section{Intro}\\label{intro}\n\n\\subsection{A}\n\n\\paragraph{B}\n\n\
+             Text\n"
+        );
+        assert_eq!(
+            latex(
+                "# A\n\n###### F",
+                &Options {
+                    chapters: true,
+                    ..options
+                }
+            ),
+            "\\chapter{A}\n\n\\subparagraph{F}\n"
+        );
+        assert_eq!(
+            latex("# A {#a_b\\c}", &options),
+            "\\section{A}\\label{a-b-c}\n"
+        );
+        assert_eq!(
+            latex("> a\n> b\n\n* x\n  * y\n* z", &options),
+            "\\begin{quote}\na\nb\n\\end{quote}\n\n\
+             \\begin{itemize}\n\\item x\n\\begin{itemize}\n\\item y\n\\end{itemize}\n\
+             \\item z\n\\end{itemize}\n"
+        );
+        assert_eq!(
+            latex("3. a\n\n4. b", &options),
+            "\\begin{enumerate}\n\\setcounter{enumi}{2}\n\\item a\n\\item b\n\
+             \\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_heading_levels() {
+        let source = "# 1\n\n## 2\n\n### 3\n\n#### 4\n\n##### 5\n\n###### 6";
+        assert_eq!(
+            latex(source, &Options::default()),
+            "\\section{1}\n\n\\subsection{2}\n\n\\subsubsection{3}\n\n\\paragraph{4}\n\n\
+             \\subparagraph{5}\n\n\\subparagraph*{6}\n"
+        );
+        let options = Options {
+            chapters: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            latex(source, &options),
+            "\\chapter{1}\n\n\\section{2}\n\n\\subsection{3}\n\n\\subsubsection{4}\n\n\
+             \\paragraph{5}\n\n\\subparagraph{6}\n"
+        );
+    }
+
+    #[test]
+    fn test_optional_arguments() {
+        // Brackets and stars after \item and \\ are kept as text
+        let options = Options::default();
+        assert_eq!(
+            latex("* [x] done\n* [ ] todo", &options),
+            "\\begin{itemize}\n\\item {}[x] done\n\\item {}[ ] todo\n\\end{itemize}\n"
+        );
+        assert_eq!(
+            latex("a  \n[b] c  \n*d*", &options),
+            "a\\\\\n{}[b] c\\\\\n{}*d*\n"
+        );
+        assert_eq!(latex("a [b]  \nc", &options), "a [b]\\\\\nc\n");
+    }
+
+    #[test]
+    fn test_inlines() {
+        let options = Options::default();
+        assert_eq!(
+            latex("50% of #1 & a_b {x} ~y^2^ \\ $a_1^{2}$", &options),
+            "50\\% of \\#1 \\& a\\_b \\{x\\} \\textasciitilde{}y\\textsuperscript{2} \
+             \\textbackslash{} $a_1^{2}$\n"
+        );
+        assert_eq!(
+            latex("a  \nb ~~c~~ {++d++}{>>e<<} <b>", &options),
+            "a\\\\\nb \\sout{c} \\uline{d}\\marginpar{e} \n"
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        let source = "[[Page#Part|a_b]] ![[fig.png]]";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render(source, &doc, Some(&Pages), &Options::default()),
+            "\\href{/wiki/Page\\#Part}{a\\_b} \\includegraphics{/wiki/fig.png}\n"
+        );
+        let source = "![[50%_{1}.png#x]]";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render(source, &doc, None, &Options::default()),
+            "\\includegraphics{50_1.png}\n"
+        );
+    }
+
+    #[test]
+    fn test_standalone() {
+        let options = Options {
+            standalone: true,
+            ..Options::default()
+        };
+        let result = latex("Hi", &options);
+        assert!(result.starts_with("\\documentclass{article}\n"));
+        assert!(result.ends_with("\\begin{document}\n\nHi\n\n\\end{document}\n"));
+    }
+}
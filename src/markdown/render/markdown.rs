@@ -0,0 +1,674 @@
+use crate::markdown::render::{
+    continuation, is_blank, list_start, narrower, render_with, Renderer,
+};
+use crate::markdown::{Attributes, Kind, Node};
+
+/// How ordered list items are numbered.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Numbering {
+    /// Count up from the number of the first item
+    #[default]
+    Increment,
+    /// Repeat the number of the first item
+    Same,
+}
+
+/// How headings are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Headings {
+    /// Start the line with a `#` for every level
+    #[default]
+    Atx,
+    /// Underline levels 1 and 2 with `=` and `-`, others stay ATX
+    Setext,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Bullet for unordered list items, one of `*`, `-` and `+`
+    pub bullet: char,
+    /// How ordered list items are numbered
+    pub numbering: Numbering,
+    /// Delimiter after ordered list numbers, `.` or `)`
+    pub delimiter: char,
+    /// How headings are written
+    pub headings: Headings,
+    /// Wrap paragraphs at this many columns, 0 keeps the original lines
+    pub width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bullet: '*',
+            numbering: Numbering::Increment,
+            delimiter: '.',
+            headings: Headings::Atx,
+            width: 0,
+        }
+    }
+}
+
+/// What separates a word from the one before it.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+enum Break {
+    None,
+    Space,
+    Newline,
+    Hard,
+}
+
+/// Lines formatted so far inside a container, and how its blocks are laid
+/// out.
+struct Frame {
+    lines: Vec<String>,
+    tight: bool,
+    width: usize,
+}
+
+pub struct Formatter<'a> {
+    options: &'a Options,
+    // Every open container, innermost last
+    frames: Vec<Frame>,
+    // Start of every open list, if ordered, the items written in it and
+    // whether it is loose
+    lists: Vec<(Option<u64>, u64, bool)>,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    pub fn new(options: &'a Options) -> Self {
+        Formatter {
+            options,
+            frames: vec![],
+            lists: vec![],
+            out: String::new(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.frames.last().map_or(self.options.width, |f| f.width)
+    }
+
+    fn push_frame(&mut self, tight: bool, width: usize) {
+        self.frames.push(Frame {
+            lines: vec![],
+            tight,
+            width,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Vec<String> {
+        self.frames.pop().map(|f| f.lines).unwrap_or_default()
+    }
+
+    /// Adds the lines of a block, after a blank line unless the container
+    /// is tight.
+    fn block(&mut self, block: Vec<String>) {
+        if let Some(frame) = self.frames.last_mut() {
+            if !frame.lines.is_empty() && !frame.tight {
+                frame.lines.push(String::new());
+            }
+            frame.lines.extend(block);
+        }
+    }
+
+    /// The marker of the next item in the innermost list.
+    fn marker(&self) -> String {
+        match (self.lists.last(), self.options.numbering) {
+            (Some(&(Some(n), i, _)), Numbering::Increment) => {
+                format!("{}{} ", n + i, self.options.delimiter)
+            }
+            (Some(&(Some(n), _, _)), Numbering::Same) => {
+                format!("{}{} ", n, self.options.delimiter)
+            }
+            _ => format!("{} ", self.options.bullet),
+        }
+    }
+
+    fn enter_list(&mut self, start: Option<u64>, loose: bool) {
+        self.lists.push((start, 0, loose));
+        self.push_frame(false, self.width());
+    }
+
+    fn leave_list(&mut self) {
+        self.lists.pop();
+        let lines = self.pop_frame();
+        self.block(lines);
+    }
+
+    /// Lays out the words of a paragraph. Soft breaks are kept as they are
+    /// unless a width is given, then the words are filled up to it.
+    fn paragraph(&self, source: &str, nodes: &[Node], width: usize) -> Vec<String> {
+        let mut lines = vec![];
+        let mut line = String::new();
+        for (b, word) in self.words(source, nodes) {
+            // A word that would open a block must not start a line
+            let b = match b {
+                Break::Newline | Break::Hard if is_block_start(&word) => Break::Space,
+                b => b,
+            };
+            let fits = line.chars().count() + 1 + word.chars().count() <= width;
+            match b {
+                Break::None => {}
+                // A list marker alone on a line is only text while nothing
+                // follows it
+                _ if is_marker(&line) => lines.push(std::mem::take(&mut line)),
+                Break::Hard => {
+                    line.push_str("  ");
+                    lines.push(std::mem::take(&mut line));
+                }
+                Break::Newline if width == 0 => lines.push(std::mem::take(&mut line)),
+                _ if width == 0 || fits || is_block_start(&word) => line.push(' '),
+                _ => lines.push(std::mem::take(&mut line)),
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+        lines
+    }
+
+    /// Splits inline content into words at the whitespace in its text.
+    /// Other inline nodes are never broken up.
+    fn words(&self, source: &str, nodes: &[Node]) -> Vec<(Break, String)> {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut before = Break::None;
+        let mut pending = Break::None;
+        let mut spaces = 0;
+        let mut line_start = false;
+        for node in nodes {
+            let (start, end) = node.span;
+            let text = &source[start..end];
+            let plain = matches!(node.kind, Kind::Plaintext | Kind::Whitespace);
+            let chunks: Vec<&str> = match plain {
+                true => text.split_inclusive(|_| true).collect(),
+                false => vec![text],
+            };
+            for chunk in chunks {
+                match chunk {
+                    "\n" if plain => {
+                        let b = if spaces >= 2 {
+                            Break::Hard
+                        } else {
+                            Break::Newline
+                        };
+                        if b > pending {
+                            pending = b;
+                        }
+                        spaces = 0;
+                        line_start = true;
+                    }
+                    // Block quote markers of continuation lines
+                    ">" if plain && line_start => {}
+                    c if plain && c.trim().is_empty() => {
+                        if pending < Break::Space {
+                            pending = Break::Space;
+                        }
+                        spaces += if c == " " { 1 } else { 0 };
+                    }
+                    c => {
+                        if pending != Break::None && !word.is_empty() {
+                            words.push((before, std::mem::take(&mut word)));
+                            before = pending;
+                        }
+                        pending = Break::None;
+                        spaces = 0;
+                        line_start = false;
+                        word.push_str(&collapse(c));
+                    }
+                }
+            }
+        }
+        if !word.is_empty() {
+            words.push((before, word));
+        }
+        words
+    }
+
+    fn attributes(&self, source: &str, attributes: &Attributes) -> String {
+        let text = |(s, e): (usize, usize)| &source[s..e];
+        let mut items = vec![];
+        if let Some(id) = attributes.id {
+            items.push(format!("#{}", text(id)));
+        }
+        for &class in &attributes.classes {
+            items.push(format!(".{}", text(class)));
+        }
+        for &(key, value) in &attributes.pairs {
+            let value = text(value);
+            let value = match value {
+                v if v.contains('"') => format!("'{}'", v),
+                v if v.is_empty()
+                    || v.contains(|c: char| c.is_whitespace() || "{}'=".contains(c)) =>
+                {
+                    format!("\"{}\"", v)
+                }
+                v => v.to_string(),
+            };
+            items.push(format!("{}={}", text(key), value));
+        }
+        format!("{{{}}}", items.join(" "))
+    }
+}
+
+impl<'a> Renderer for Formatter<'a> {
+    type Output = String;
+
+    fn finish(&mut self) -> String {
+        self.frames.clear();
+        self.lists.clear();
+        std::mem::take(&mut self.out)
+    }
+
+    fn enter_document(&mut self, _source: &str, _node: &Node) {
+        self.push_frame(false, self.options.width);
+    }
+
+    fn leave_document(&mut self, _source: &str, _node: &Node) {
+        self.out = self.pop_frame().join("\n");
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn enter_block_quote(&mut self, _source: &str, _node: &Node) {
+        self.push_frame(false, narrower(self.width(), 2));
+    }
+
+    fn leave_block_quote(&mut self, _source: &str, _node: &Node) {
+        let mut lines: Vec<String> = self
+            .pop_frame()
+            .into_iter()
+            .map(|l| match l.is_empty() {
+                true => ">".to_string(),
+                false => format!("> {}", l),
+            })
+            .collect();
+        // An empty quote is still written as one
+        if lines.is_empty() {
+            lines.push(">".to_string());
+        }
+        self.block(lines);
+    }
+
+    fn enter_unordered_list(&mut self, _source: &str, _node: &Node, loose: bool) {
+        self.enter_list(None, loose);
+    }
+
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.leave_list();
+    }
+
+    fn enter_ordered_list(&mut self, source: &str, node: &Node, loose: bool) {
+        let start = list_start(source, node.span.0).unwrap_or(1);
+        self.enter_list(Some(start), loose);
+    }
+
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.leave_list();
+    }
+
+    fn enter_list_item(&mut self, _source: &str, _node: &Node) {
+        let loose = self.lists.last().is_some_and(|&(_, _, loose)| loose);
+        let columns = self.marker().len();
+        self.push_frame(!loose, narrower(self.width(), columns));
+    }
+
+    fn leave_list_item(&mut self, _source: &str, node: &Node) {
+        let marker = self.marker();
+        let mut body = self.pop_frame().into_iter();
+        let (_, i, loose) = self.lists.last().copied().unwrap_or((None, 0, false));
+        if let Some(list) = self.lists.last_mut() {
+            list.1 += 1;
+        }
+        let mut lines = vec![];
+        if i > 0 && loose {
+            lines.push(String::new());
+        }
+        let indent = " ".repeat(marker.len());
+        // An item starting with a blank line keeps its content on the next
+        let blank = node.children.first().is_none_or(is_blank);
+        match blank {
+            true => lines.push(marker),
+            false => lines.push(format!("{}{}", marker, body.next().unwrap_or_default())),
+        }
+        for line in body {
+            match line.is_empty() {
+                true => lines.push(line),
+                false => lines.push(format!("{}{}", indent, line)),
+            }
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.lines.extend(lines);
+        }
+    }
+
+    fn leave_heading(&mut self, source: &str, node: &Node, level: usize) {
+        let words = self.words(source, &node.children);
+        let text = words
+            .into_iter()
+            .map(|(_, w)| w)
+            .collect::<Vec<_>>()
+            .join(" ");
+        // Only ATX headings take attributes, the text must not open a block
+        // of its own, and without a blank line before it the text would
+        // continue the block above
+        let setext = self.options.headings == Headings::Setext
+            && level <= 2
+            && node.attributes.is_none()
+            && text
+                .split(' ')
+                .next()
+                .is_some_and(|w| !w.is_empty() && !is_block_start(w))
+            && self
+                .frames
+                .last()
+                .is_some_and(|f| !f.tight || f.lines.is_empty());
+        if setext {
+            let underline = match level {
+                1 => "=",
+                _ => "-",
+            };
+            let width = text.chars().count().max(3);
+            self.block(vec![text, underline.repeat(width)]);
+            return;
+        }
+        let mut line = "#".repeat(level);
+        if !text.is_empty() {
+            line.push(' ');
+            line.push_str(&text);
+        }
+        if let Some(attributes) = &node.attributes {
+            line.push(' ');
+            line.push_str(&self.attributes(source, attributes));
+        }
+        self.block(vec![line]);
+    }
+
+    // Inline content is laid out from the source of the paragraph's
+    // children, so the inline hooks are left empty
+    fn leave_paragraph(&mut self, source: &str, node: &Node) {
+        let lines = self.paragraph(source, &node.children, self.width());
+        self.block(lines);
+    }
+}
+
+/// Whether a line starting with `word` and more words after it would open
+/// a block instead of continuing a paragraph. Runs of `=` and `-` would
+/// underline the line before them when alone.
+fn is_block_start(word: &str) -> bool {
+    word.starts_with('>')
+        || ['#', '=', '-']
+            .iter()
+            .any(|&c| word.chars().all(|w| w == c))
+        || is_marker(word)
+}
+
+fn is_marker(word: &str) -> bool {
+    let number = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    matches!(word, "*" | "-" | "+") || number.len() < word.len() && matches!(number, "." | ")")
+}
+
+/// Joins the lines of an inline node that is kept in one piece.
+fn collapse(s: &str) -> String {
+    match s.contains('\n') {
+        true => s
+            .split('\n')
+            .enumerate()
+            .map(|(i, l)| match i {
+                0 => l.trim_end_matches([' ', '\t']),
+                _ => continuation(l).trim_end_matches([' ', '\t']),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        false => s.to_string(),
+    }
+}
+
+/// Writes the tree back out as canonical markdown.
+pub fn render(source: &str, node: &Node, options: &Options) -> String {
+    render_with(&mut Formatter::new(options), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::{parse, Options as ParseOptions};
+    use proptest::prelude::*;
+
+    fn parse_options() -> ParseOptions {
+        ParseOptions {
+            math: true,
+            mentions: true,
+            hashtags: true,
+            critic_markup: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    fn format(source: &str, options: &Options) -> String {
+        render(source, &parse(source, &parse_options()), options)
+    }
+
+    /// The tree without source offsets or blank lines, with whitespace in
+    /// text collapsed.
+    fn shape(source: &str, node: &Node) -> String {
+        let kind = format!("{:?}", node.kind);
+        let mut result = String::new();
+        let mut rest = &kind[..];
+        // Spans look like "(12, 34)"
+        while let Some(i) = rest.find('(') {
+            let end = rest[i..].find(')').map(|e| i + e);
+            let span = end.map(|e| &rest[i + 1..e]);
+            result.push_str(&rest[..i]);
+            match span.filter(|s| s.split(", ").all(|n| n.parse::<usize>().is_ok())) {
+                Some(s) if s.contains(", ") => {
+                    result.push('_');
+                    rest = &rest[end.unwrap() + 1..];
+                }
+                _ => {
+                    result.push('(');
+                    rest = &rest[i + 1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        if node.kind == Kind::Plaintext {
+            let (s, e) = node.span;
+            let lines = source[s..e].split('\n').enumerate();
+            let lines = lines.map(|(i, l)| if i == 0 { l } else { continuation(l) });
+            let text = lines.flat_map(str::split_whitespace).collect::<Vec<_>>();
+            result.push_str(&format!(" {:?}", text.join(" ")));
+        }
+        let children = node
+            .children
+            .iter()
+            .filter(|n| !is_blank(n))
+            .map(|n| shape(source, n))
+            .collect::<Vec<_>>();
+        match children.is_empty() {
+            true => result,
+            false => format!("{} [{}]", result, children.join(", ")),
+        }
+    }
+
+    fn assert_formats(source: &str, options: &Options) {
+        let formatted = format(source, options);
+        let options_p = parse_options();
+        assert_eq!(
+            shape(&formatted, &parse(&formatted, &options_p)),
+            shape(source, &parse(source, &options_p)),
+            "{:?} formatted as {:?}",
+            source,
+            formatted
+        );
+        assert_eq!(format(&formatted, options), formatted, "{:?}", source);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let options = Options::default();
+        assert_eq!(
+            format("#   A  {#a  .b}\nText\n", &options),
+            "# A {#a .b}\n\nText\n"
+        );
+        assert_eq!(
+            format("- a\n-   b\n    c\n\n\n> q\nlazy", &options),
+            "* a\n* b\n  c\n\n> q\n> lazy\n"
+        );
+        assert_eq!(
+            format(
+                "3) a\n\n3) b",
+                &Options {
+                    numbering: Numbering::Increment,
+                    ..options
+                }
+            ),
+            "3. a\n\n4. b\n"
+        );
+        assert_eq!(format("* \n  * a", &options), "* \n  * a\n");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let options = Options {
+            width: 10,
+            ..Options::default()
+        };
+        assert_eq!(
+            format("aaa bbb ccc ~~d e~~ f  \ng - h", &options),
+            "aaa bbb\nccc\n~~d e~~ f  \ng - h\n"
+        );
+        assert_eq!(
+            format("* aaaa bbbb cccc", &options),
+            "* aaaa\n  bbbb\n  cccc\n"
+        );
+    }
+
+    #[test]
+    fn test_setext() {
+        let options = Options {
+            headings: Headings::Setext,
+            ..Options::default()
+        };
+        assert_eq!(
+            format("# A b\n## C\n### D\n", &options),
+            "A b\n===\n\nC\n---\n\n### D\n"
+        );
+        assert_eq!(format("Title\n---", &Options::default()), "## Title\n");
+        // Attributes and text that opens a block keep the ATX form, so do
+        // headings right after another block in a tight item
+        assert_eq!(format("# A {#a}", &options), "# A {#a}\n");
+        assert_eq!(format("# - a", &options), "# - a\n");
+        assert_eq!(format("* a\n  # B", &options), "* a\n  # B\n");
+        for source in ["# A b\n## C", "* # A\n  b", "> # A"] {
+            assert_formats(source, &options);
+        }
+    }
+
+    #[test]
+    fn test_block_like_lines() {
+        let options = Options::default();
+        assert_eq!(format(">", &options), ">\n");
+        assert_eq!(format("a\n>", &options), "a\n\n>\n");
+        assert_eq!(format("* a\n#t", &options), "* a\n  #t\n");
+        let wrapped = Options {
+            width: 20,
+            ..options
+        };
+        assert_eq!(format("-\n#t\nb -", &wrapped), "-\n#t b -\n");
+        for source in [">", "a\n>", "* a\n#t", "-\n#t\nb -", "1.\n*\nb"] {
+            assert_formats(source, &options);
+            assert_formats(source, &wrapped);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_formats("# A\n\n* a\n  * b\n\n> c\n> d", &Options::default());
+        assert_formats("1. a\n\n2. b\n\n   c", &Options::default());
+    }
+
+    fn word() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec![
+            "a",
+            "bb",
+            "ccc",
+            "dddd",
+            "1.5",
+            "x*y",
+            "-",
+            "#",
+            "#tag",
+            "@me",
+            ":tada:",
+            "it's",
+            "~~del~~",
+            "$x$",
+            "[[Page]]",
+            "[[P|label]]",
+            "<b>",
+            "</b>",
+            "{++add++}",
+            "2.",
+            "=",
+            "===",
+            "---",
+        ])
+    }
+
+    /// Lines open with a plain word or with something that would start a
+    /// block at the beginning of a line.
+    fn line() -> impl Strategy<Value = String> {
+        let first = prop::sample::select(vec![
+            "a", "bb", "ccc", "it's", "~~del~~", "[[Page]]", ">", "> >", "#", "# a", "#t", "*",
+            "* a", "-", "- a", "+", "1.", "1. a", "2)",
+        ]);
+        (first, prop::collection::vec(word(), 0..8))
+            .prop_map(|(first, w)| format!("{} {}", first, w.join(" ")).trim_end().to_string())
+    }
+
+    fn block() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::collection::vec(line(), 1..4).prop_map(|l| l.join("\n")),
+            (1..4usize, line()).prop_map(|(n, l)| format!("{} {}", "#".repeat(n), l)),
+            prop::collection::vec(line(), 1..4)
+                .prop_map(|l| l.iter().map(|l| format!("* {}\n", l)).collect()),
+            prop::collection::vec(line(), 1..4).prop_map(|l| l
+                .iter()
+                .enumerate()
+                .map(|(i, l)| format!("{}. {}\n", i + 1, l))
+                .collect()),
+            prop::collection::vec(line(), 1..4)
+                .prop_map(|l| l.iter().map(|l| format!("> {}\n", l)).collect()),
+            // Continuation lines, indented or lazy
+            (line(), line(), line()).prop_map(|(a, b, c)| format!("* {}\n  {}\n{}\n", a, b, c)),
+            (line(), line()).prop_map(|(a, b)| format!("> {}\n{}\n", a, b)),
+            (line(), prop::sample::select(vec!["=", "===", "-", "---"]))
+                .prop_map(|(l, u)| format!("{}\n{}\n", l, u)),
+        ]
+    }
+
+    fn document() -> impl Strategy<Value = String> {
+        prop::collection::vec(block(), 1..5).prop_map(|b| b.join("\n\n"))
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip(source in document()) {
+            assert_formats(&source, &Options::default());
+        }
+
+        #[test]
+        fn prop_round_trip_wrapped(source in document(), width in 8..40usize) {
+            assert_formats(&source, &Options {
+                width,
+                bullet: '-',
+                numbering: Numbering::Same,
+                headings: Headings::Setext,
+                ..Options::default()
+            });
+        }
+    }
+}
@@ -1 +1,287 @@
+use crate::markdown::{Html, Kind, Node};
+
+pub mod ansi;
+pub mod binary;
+pub mod html;
+pub mod js;
 pub mod json;
+pub mod latex;
+pub mod markdown;
+pub mod text;
+
+/// The result of looking up a wiki link target.
+pub struct Resolution {
+    pub url: String,
+    pub exists: bool,
+}
+
+/// Maps wiki link targets to URLs while rendering.
+pub trait Resolver {
+    fn resolve(&self, target: &str) -> Option<Resolution>;
+}
+
+/// An output format built from a walk over the tree, with the source the
+/// spans point into. Every kind of container has a hook called before its
+/// children and one called after them, leaves have a single hook. `enter`
+/// and `leave` dispatch to the hooks, a renderer that handles every node
+/// alike overrides them instead.
+pub trait Renderer {
+    type Output;
+
+    fn enter(&mut self, source: &str, node: &Node) {
+        match node.kind {
+            Kind::Document => self.enter_document(source, node),
+            Kind::BlockQuote => self.enter_block_quote(source, node),
+            Kind::Empty => self.enter_empty(source, node),
+            Kind::UnorderedList(loose) => self.enter_unordered_list(source, node, loose),
+            Kind::OrderedList(loose) => self.enter_ordered_list(source, node, loose),
+            Kind::ListItem => self.enter_list_item(source, node),
+            Kind::Heading(level) => self.enter_heading(source, node, level),
+            Kind::Paragraph => self.enter_paragraph(source, node),
+            Kind::EmptyLine => self.empty_line(source, node),
+            Kind::Plaintext => self.plaintext(source, node),
+            Kind::Whitespace => self.whitespace(source, node),
+            Kind::Strikethrough => self.enter_strikethrough(source, node),
+            Kind::MathInline => self.math_inline(source, node),
+            Kind::WikiLink { .. } => self.wiki_link(source, node),
+            Kind::Mention => self.mention(source, node),
+            Kind::Hashtag => self.hashtag(source, node),
+            Kind::Emoji(emoji) => self.emoji(source, node, emoji),
+            Kind::Highlight => self.enter_highlight(source, node),
+            Kind::Superscript => self.enter_superscript(source, node),
+            Kind::Subscript => self.enter_subscript(source, node),
+            Kind::InlineHtml(html) => self.inline_html(source, node, html),
+            Kind::CriticAddition => self.enter_critic_addition(source, node),
+            Kind::CriticDeletion => self.enter_critic_deletion(source, node),
+            Kind::CriticSubstitution => self.enter_critic_substitution(source, node),
+            Kind::CriticHighlight => self.enter_critic_highlight(source, node),
+            Kind::CriticComment => self.critic_comment(source, node),
+            Kind::SmartPunctuation(s) => self.smart_punctuation(source, node, s),
+        }
+    }
+
+    fn leave(&mut self, source: &str, node: &Node) {
+        match node.kind {
+            Kind::Document => self.leave_document(source, node),
+            Kind::BlockQuote => self.leave_block_quote(source, node),
+            Kind::Empty => self.leave_empty(source, node),
+            Kind::UnorderedList(loose) => self.leave_unordered_list(source, node, loose),
+            Kind::OrderedList(loose) => self.leave_ordered_list(source, node, loose),
+            Kind::ListItem => self.leave_list_item(source, node),
+            Kind::Heading(level) => self.leave_heading(source, node, level),
+            Kind::Paragraph => self.leave_paragraph(source, node),
+            Kind::Strikethrough => self.leave_strikethrough(source, node),
+            Kind::Highlight => self.leave_highlight(source, node),
+            Kind::Superscript => self.leave_superscript(source, node),
+            Kind::Subscript => self.leave_subscript(source, node),
+            Kind::CriticAddition => self.leave_critic_addition(source, node),
+            Kind::CriticDeletion => self.leave_critic_deletion(source, node),
+            Kind::CriticSubstitution => self.leave_critic_substitution(source, node),
+            Kind::CriticHighlight => self.leave_critic_highlight(source, node),
+            _ => {}
+        }
+    }
+
+    /// Takes the output once every node has been left.
+    fn finish(&mut self) -> Self::Output;
+
+    // Container blocks
+    fn enter_document(&mut self, _source: &str, _node: &Node) {}
+    fn leave_document(&mut self, _source: &str, _node: &Node) {}
+    fn enter_block_quote(&mut self, _source: &str, _node: &Node) {}
+    fn leave_block_quote(&mut self, _source: &str, _node: &Node) {}
+    /// Blank lines between blocks, each an `empty_line`
+    fn enter_empty(&mut self, _source: &str, _node: &Node) {}
+    fn leave_empty(&mut self, _source: &str, _node: &Node) {}
+    fn enter_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {}
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {}
+    fn enter_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {}
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {}
+    fn enter_list_item(&mut self, _source: &str, _node: &Node) {}
+    fn leave_list_item(&mut self, _source: &str, _node: &Node) {}
+
+    // Leaf blocks
+    fn enter_heading(&mut self, _source: &str, _node: &Node, _level: usize) {}
+    fn leave_heading(&mut self, _source: &str, _node: &Node, _level: usize) {}
+    fn enter_paragraph(&mut self, _source: &str, _node: &Node) {}
+    fn leave_paragraph(&mut self, _source: &str, _node: &Node) {}
+    fn empty_line(&mut self, _source: &str, _node: &Node) {}
+
+    // Inlines
+    fn plaintext(&mut self, _source: &str, _node: &Node) {}
+    fn whitespace(&mut self, _source: &str, _node: &Node) {}
+    fn enter_strikethrough(&mut self, _source: &str, _node: &Node) {}
+    fn leave_strikethrough(&mut self, _source: &str, _node: &Node) {}
+    fn math_inline(&mut self, _source: &str, _node: &Node) {}
+    fn wiki_link(&mut self, _source: &str, _node: &Node) {}
+    fn mention(&mut self, _source: &str, _node: &Node) {}
+    fn hashtag(&mut self, _source: &str, _node: &Node) {}
+    fn emoji(&mut self, _source: &str, _node: &Node, _emoji: &'static str) {}
+    fn enter_highlight(&mut self, _source: &str, _node: &Node) {}
+    fn leave_highlight(&mut self, _source: &str, _node: &Node) {}
+    fn enter_superscript(&mut self, _source: &str, _node: &Node) {}
+    fn leave_superscript(&mut self, _source: &str, _node: &Node) {}
+    fn enter_subscript(&mut self, _source: &str, _node: &Node) {}
+    fn leave_subscript(&mut self, _source: &str, _node: &Node) {}
+    fn inline_html(&mut self, _source: &str, _node: &Node, _html: Html) {}
+    fn enter_critic_addition(&mut self, _source: &str, _node: &Node) {}
+    fn leave_critic_addition(&mut self, _source: &str, _node: &Node) {}
+    fn enter_critic_deletion(&mut self, _source: &str, _node: &Node) {}
+    fn leave_critic_deletion(&mut self, _source: &str, _node: &Node) {}
+    /// The deletion and addition it replaces it with are its children
+    fn enter_critic_substitution(&mut self, _source: &str, _node: &Node) {}
+    fn leave_critic_substitution(&mut self, _source: &str, _node: &Node) {}
+    fn enter_critic_highlight(&mut self, _source: &str, _node: &Node) {}
+    fn leave_critic_highlight(&mut self, _source: &str, _node: &Node) {}
+    fn critic_comment(&mut self, _source: &str, _node: &Node) {}
+    fn smart_punctuation(&mut self, _source: &str, _node: &Node, _replacement: &'static str) {}
+}
+
+/// Walks the tree depth first and returns what the renderer made of it.
+pub fn render_with<R>(renderer: &mut R, source: &str, node: &Node) -> R::Output
+where
+    R: Renderer + ?Sized,
+{
+    walk(renderer, source, node);
+    renderer.finish()
+}
+
+pub(crate) fn walk<R: Renderer + ?Sized>(renderer: &mut R, source: &str, node: &Node) {
+    renderer.enter(source, node);
+    for child in &node.children {
+        walk(renderer, source, child);
+    }
+    renderer.leave(source, node);
+}
+
+/// The number in front of the first item of an ordered list, whose span
+/// starts after the marker.
+pub(crate) fn list_start(source: &str, start: usize) -> Option<u64> {
+    let marker = source[..start].trim_end_matches([' ', '\t']);
+    let number = marker.strip_suffix(['.', ')'])?;
+    let digits = number.trim_end_matches(|c: char| c.is_ascii_digit());
+    number[digits.len()..].parse().ok()
+}
+
+/// Strips the indentation and block quote markers in front of a line that
+/// continues a paragraph. Node spans cover them since they are contiguous.
+pub(crate) fn continuation(line: &str) -> &str {
+    line.trim_start_matches([' ', '\t', '>'])
+}
+
+/// Blank lines between blocks are kept in the tree as nodes of their own.
+pub(crate) fn is_blank(node: &Node) -> bool {
+    matches!(node.kind, Kind::Empty | Kind::EmptyLine)
+}
+
+/// The width left after indenting by `by` columns, 0 stays unlimited.
+pub(crate) fn narrower(width: usize, by: usize) -> usize {
+    match width {
+        0 => 0,
+        _ => width.saturating_sub(by).max(1),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::markdown::parse;
+
+    /// Resolves every target under `/wiki/`, all but `Missing` exist.
+    pub(crate) struct Pages;
+
+    impl Resolver for Pages {
+        fn resolve(&self, target: &str) -> Option<Resolution> {
+            Some(Resolution {
+                url: format!("/wiki/{}", target),
+                exists: target != "Missing",
+            })
+        }
+    }
+
+    /// A short document using most of the syntax `options()` turns on.
+    pub(crate) const SOURCE: &str = "# Intro {#top .a k=v}
+
+Some ~~struck~~ text, $x^2$, @me #tag :tada: \"quoted\" -- <b>bold</b> <!-- c -->
+[[Home]] [[Missing#Part|label]] ![[cat.png]] {++add++}{~~a~>b~~}{>>note<<}
+
+> * nested
+>
+> * loose
+>   1. deep
+";
+
+    /// Records the order nodes are entered and left in.
+    struct Trace(Vec<String>);
+
+    impl Renderer for Trace {
+        type Output = String;
+
+        fn enter(&mut self, _source: &str, node: &Node) {
+            self.0.push(format!("+{:?}", node.kind));
+        }
+
+        fn leave(&mut self, _source: &str, node: &Node) {
+            self.0.push(format!("-{:?}", node.kind));
+        }
+
+        fn finish(&mut self) -> String {
+            self.0.join(" ")
+        }
+    }
+
+    /// Lists headings by level, using only the per-kind hooks.
+    #[derive(Default)]
+    struct Outline {
+        lines: Vec<String>,
+        heading: Option<String>,
+    }
+
+    impl Renderer for Outline {
+        type Output = String;
+
+        fn enter_heading(&mut self, _source: &str, _node: &Node, level: usize) {
+            self.heading = Some("  ".repeat(level - 1));
+        }
+
+        fn leave_heading(&mut self, _source: &str, _node: &Node, _level: usize) {
+            self.lines.extend(self.heading.take());
+        }
+
+        fn plaintext(&mut self, source: &str, node: &Node) {
+            if let Some(heading) = &mut self.heading {
+                heading.push_str(&source[node.span.0..node.span.1]);
+            }
+        }
+
+        fn whitespace(&mut self, _source: &str, _node: &Node) {
+            if let Some(heading) = &mut self.heading {
+                heading.push(' ');
+            }
+        }
+
+        fn finish(&mut self) -> String {
+            self.lines.join("\n")
+        }
+    }
+
+    #[test]
+    fn test_render_with() {
+        let source = "# A";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render_with(&mut Trace(vec![]), source, &doc),
+            "+Document +Heading(1) +Plaintext -Plaintext -Heading(1) -Document"
+        );
+    }
+
+    #[test]
+    fn test_kind_hooks() {
+        let source = "# A b\n\ntext\n\n## C\n\n> ### D\n";
+        let doc = parse(source, &Default::default());
+        assert_eq!(
+            render_with(&mut Outline::default(), source, &doc),
+            "A b\n  C\n    D"
+        );
+    }
+}
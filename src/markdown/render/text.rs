@@ -0,0 +1,370 @@
+use serde::Serialize;
+
+use crate::markdown::render::{continuation, list_start, render_with, Renderer};
+use crate::markdown::{Kind, Node};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Write the alt text of embedded images
+    pub alt_text: bool,
+}
+
+/// A run of output text and the source it was written from. Copied text
+/// maps offsets one to one, text that replaced its source, such as emoji
+/// and smart punctuation, only maps as a whole.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct Segment {
+    pub output: (usize, usize),
+    pub source: (usize, usize),
+    pub copied: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Text {
+    pub text: String,
+    pub map: Vec<Segment>,
+}
+
+impl Text {
+    /// Maps a byte range of the text back to the source. Text that was not
+    /// written from the source, such as bullets, has no span.
+    pub fn source_span(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let (s, _) = self.source_offset(start)?;
+        let (_, e) = self.source_offset(end.max(start + 1) - 1)?;
+        Some((s, e.max(s)))
+    }
+
+    /// The source range for the output byte at `offset`, a single byte when
+    /// the text was copied and the whole span when it was replaced.
+    fn source_offset(&self, offset: usize) -> Option<(usize, usize)> {
+        let i = self.map.partition_point(|s| s.output.1 <= offset);
+        let segment = self.map.get(i).filter(|s| s.output.0 <= offset)?;
+        match segment.copied {
+            true => {
+                let p = segment.source.0 + offset - segment.output.0;
+                Some((p, p + 1))
+            }
+            false => Some(segment.source),
+        }
+    }
+}
+
+pub struct Writer<'a> {
+    options: &'a Options,
+    out: String,
+    map: Vec<Segment>,
+    // Indentation of the lines in every open container, and whether its
+    // blocks are tight
+    blocks: Vec<(String, bool)>,
+    // Start of every open list, if ordered, and the items written in it
+    lists: Vec<(Option<u64>, u64)>,
+    // Set after a list marker, the next block continues its line
+    at_marker: bool,
+    // Number of enclosing deletions, whose text is left out
+    deleted: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(options: &'a Options) -> Self {
+        Writer {
+            options,
+            out: String::new(),
+            map: vec![],
+            blocks: vec![],
+            lists: vec![],
+            at_marker: false,
+            deleted: 0,
+        }
+    }
+
+    /// Starts a new block, on the next line when `tight` and after a blank
+    /// line otherwise.
+    fn separate(&mut self, tight: bool) {
+        if self.at_marker {
+            self.at_marker = false;
+            return;
+        }
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.push_str(if tight { "\n" } else { "\n\n" });
+        if let Some((indent, _)) = self.blocks.last() {
+            self.out.push_str(indent);
+        }
+    }
+
+    fn block(&mut self) {
+        let tight = self.blocks.last().is_some_and(|(_, tight)| *tight);
+        self.separate(tight);
+    }
+
+    /// Copies text, joining soft line breaks with a space.
+    fn text(&mut self, source: &str, start: usize, end: usize) {
+        let mut p = start;
+        let text = &source[start..end];
+        for (i, line) in text.split('\n').enumerate() {
+            let mut line_start = p;
+            if i > 0 {
+                let trimmed = continuation(line);
+                line_start += line.len() - trimmed.len();
+                // Two trailing spaces are a hard break
+                let hard = self.out.ends_with("  ");
+                self.trim_end();
+                self.out.push(if hard { '\n' } else { ' ' });
+            }
+            let line_end = p + line.len();
+            if line_start < line_end {
+                self.copy(source, line_start, line_end);
+            }
+            p = line_end + 1;
+        }
+    }
+
+    /// Drops trailing spaces along with their part of the map.
+    fn trim_end(&mut self) {
+        let len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(len);
+        while let Some(last) = self.map.last_mut() {
+            match last.output {
+                (start, _) if start >= len => {
+                    self.map.pop();
+                }
+                (start, end) if end > len => {
+                    last.output.1 = len;
+                    if last.copied {
+                        last.source.1 = last.source.0 + (len - start);
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn copy(&mut self, source: &str, start: usize, end: usize) {
+        self.push(&source[start..end], (start, end), true);
+    }
+
+    fn push(&mut self, text: &str, source: (usize, usize), copied: bool) {
+        if self.deleted > 0 {
+            return;
+        }
+        let start = self.out.len();
+        self.out.push_str(text);
+        self.map.push(Segment {
+            output: (start, self.out.len()),
+            source,
+            copied,
+        });
+    }
+}
+
+impl<'a> Renderer for Writer<'a> {
+    type Output = Text;
+
+    fn finish(&mut self) -> Text {
+        self.trim_end();
+        self.blocks.clear();
+        self.lists.clear();
+        self.at_marker = false;
+        self.deleted = 0;
+        Text {
+            text: std::mem::take(&mut self.out),
+            map: std::mem::take(&mut self.map),
+        }
+    }
+
+    fn enter_document(&mut self, source: &str, _node: &Node) {
+        self.out.reserve(source.len());
+        self.blocks.push((String::new(), false));
+    }
+
+    fn leave_document(&mut self, _source: &str, _node: &Node) {
+        self.blocks.pop();
+    }
+
+    fn enter_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.lists.push((None, 0));
+    }
+
+    fn leave_unordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.lists.pop();
+    }
+
+    fn enter_ordered_list(&mut self, source: &str, node: &Node, _loose: bool) {
+        let start = list_start(source, node.span.0).unwrap_or(1);
+        self.lists.push((Some(start), 0));
+    }
+
+    fn leave_ordered_list(&mut self, _source: &str, _node: &Node, _loose: bool) {
+        self.lists.pop();
+    }
+
+    fn enter_list_item(&mut self, _source: &str, _node: &Node) {
+        let (start, i) = self.lists.last().copied().unwrap_or((None, 0));
+        let marker = match start {
+            Some(n) => format!("{}. ", n + i),
+            None => "\u{2022} ".to_string(),
+        };
+        let (indent, tight) = self.blocks.last().cloned().unwrap_or_default();
+        // Items are separated by a single line break
+        self.separate(tight || i > 0);
+        self.out.push_str(&marker);
+        self.at_marker = true;
+        let indent = format!("{}{}", indent, " ".repeat(marker.chars().count()));
+        self.blocks.push((indent, true));
+        if let Some((_, i)) = self.lists.last_mut() {
+            *i += 1;
+        }
+    }
+
+    fn leave_list_item(&mut self, _source: &str, _node: &Node) {
+        self.blocks.pop();
+        self.at_marker = false;
+    }
+
+    fn enter_heading(&mut self, _source: &str, _node: &Node, _level: usize) {
+        self.block();
+    }
+
+    fn enter_paragraph(&mut self, _source: &str, _node: &Node) {
+        self.block();
+    }
+
+    fn plaintext(&mut self, source: &str, node: &Node) {
+        if self.deleted == 0 {
+            self.text(source, node.span.0, node.span.1);
+        }
+    }
+
+    fn whitespace(&mut self, source: &str, node: &Node) {
+        self.plaintext(source, node);
+    }
+
+    fn math_inline(&mut self, source: &str, node: &Node) {
+        self.copy(source, node.span.0 + 1, node.span.1 - 1);
+    }
+
+    fn wiki_link(&mut self, source: &str, node: &Node) {
+        if let Kind::WikiLink {
+            embed,
+            target,
+            anchor,
+            label,
+        } = node.kind
+        {
+            if embed && !self.options.alt_text {
+                return;
+            }
+            match label {
+                Some((s, e)) => self.copy(source, s, e),
+                None => self.copy(source, target.0, anchor.unwrap_or(target).1),
+            }
+        }
+    }
+
+    fn mention(&mut self, source: &str, node: &Node) {
+        self.copy(source, node.span.0, node.span.1);
+    }
+
+    fn hashtag(&mut self, source: &str, node: &Node) {
+        self.copy(source, node.span.0, node.span.1);
+    }
+
+    fn emoji(&mut self, _source: &str, node: &Node, emoji: &'static str) {
+        self.push(emoji, node.span, false);
+    }
+
+    // Changes read as if they were accepted, comments and raw HTML are
+    // dropped
+    fn enter_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.deleted += 1;
+    }
+
+    fn leave_critic_deletion(&mut self, _source: &str, _node: &Node) {
+        self.deleted -= 1;
+    }
+
+    fn smart_punctuation(&mut self, _source: &str, node: &Node, replacement: &'static str) {
+        self.push(replacement, node.span, false);
+    }
+}
+
+/// Writes the readable text of the document with a map back to the source.
+pub fn render(source: &str, node: &Node, options: &Options) -> Text {
+    render_with(&mut Writer::new(options), source, node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::{parse, Options as ParseOptions};
+
+    fn text(source: &str, options: &Options) -> Text {
+        let parse_options = ParseOptions {
+            math: true,
+            critic_markup: true,
+            ..ParseOptions::default()
+        };
+        render(source, &parse(source, &parse_options), options)
+    }
+
+    #[test]
+    fn test_text() {
+        let options = Options::default();
+        assert_eq!(
+            text("# Title\n\nSome ~~old~~\ntext  \nhere :tada:", &options).text,
+            "Title\n\nSome old text\nhere \u{1f389}"
+        );
+        assert_eq!(
+            text("Intro\n\n* a\n  * b\n* c\n\nMid\n\n3. d\n\n> e", &options).text,
+            "Intro\n\n\u{2022} a\n  \u{2022} b\n\u{2022} c\n\nMid\n\n3. d\n\ne"
+        );
+        assert_eq!(
+            text(
+                "See [[Page#Part]] and [[P|this]] {--x--}{++y++} <b>$z$</b>",
+                &options
+            )
+            .text,
+            "See Page#Part and this y z"
+        );
+    }
+
+    #[test]
+    fn test_alt_text() {
+        let source = "![[cat.png|A cat]] sits";
+        assert_eq!(text(source, &Options::default()).text, " sits");
+        let options = Options { alt_text: true };
+        assert_eq!(text(source, &options).text, "A cat sits");
+    }
+
+    #[test]
+    fn test_source_span() {
+        let source = "> Hello\n> world :tada: [[P|link]]";
+        let t = text(source, &Options::default());
+        assert_eq!(t.text, "Hello world \u{1f389} link");
+        let find = |s: &str| {
+            let i = t.text.find(s).unwrap();
+            t.source_span(i, i + s.len())
+        };
+        assert_eq!(find("world"), Some((10, 15)));
+        assert_eq!(find("lo wor"), Some((5, 13)));
+        assert_eq!(find("\u{1f389}"), Some((16, 22)));
+        assert_eq!(find("link"), Some((27, 31)));
+        assert_eq!(t.source_span(100, 101), None);
+    }
+
+    #[test]
+    fn test_replaced_span() {
+        // Replacements as long as their source still map only as a whole
+        let source = "Yes... :+1:";
+        let options = ParseOptions {
+            smart_punctuation: true,
+            ..ParseOptions::default()
+        };
+        let t = render(source, &parse(source, &options), &Options::default());
+        assert_eq!(t.text, "Yes\u{2026} \u{1f44d}");
+        assert_eq!(t.source_span(4, 5), Some((3, 6)));
+        assert_eq!(t.source_span(9, 10), Some((7, 11)));
+        assert_eq!(t.source_span(0, 5), Some((0, 6)));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::markdown::{Kind, Node};
+
+/// Applies every CriticMarkup change in the document.
+pub fn accept_all(source: &str, node: &Node) -> String {
+    resolve(source, node, |_| Some(true))
+}
+
+/// Drops every CriticMarkup change in the document.
+pub fn reject_all(source: &str, node: &Node) -> String {
+    resolve(source, node, |_| Some(false))
+}
+
+/// Applies the change at `span` and leaves the others untouched.
+pub fn accept(source: &str, node: &Node, span: (usize, usize)) -> String {
+    resolve(source, node, |s| if s == span { Some(true) } else { None })
+}
+
+/// Drops the change at `span` and leaves the others untouched.
+pub fn reject(source: &str, node: &Node, span: (usize, usize)) -> String {
+    resolve(source, node, |s| if s == span { Some(false) } else { None })
+}
+
+/// Rebuilds the source, replacing each change for which `decide` returns
+/// whether to accept it. Highlights and comments are removed either way.
+fn resolve<F>(source: &str, node: &Node, decide: F) -> String
+where
+    F: Fn((usize, usize)) -> Option<bool>,
+{
+    let mut changes = vec![];
+    collect(node, &mut changes);
+    let mut result = String::with_capacity(source.len());
+    let mut p = 0;
+    for change in changes {
+        let (start, end) = change.span;
+        let accept = match decide(change.span) {
+            Some(accept) => accept,
+            None => continue,
+        };
+        let inner = &source[start + 3..end - 3];
+        let text = match (&change.kind, accept) {
+            (Kind::CriticAddition, true) | (Kind::CriticDeletion, false) => inner,
+            (Kind::CriticAddition, false) | (Kind::CriticDeletion, true) => "",
+            (Kind::CriticSubstitution, accept) => {
+                let (s, e) = change.children[if accept { 1 } else { 0 }].span;
+                &source[s..e]
+            }
+            (Kind::CriticHighlight, _) => inner,
+            _ => "",
+        };
+        result.push_str(&source[p..start]);
+        result.push_str(text);
+        p = end;
+    }
+    result.push_str(&source[p..]);
+    result
+}
+
+fn collect<'a>(node: &'a Node, result: &mut Vec<&'a Node>) {
+    match node.kind {
+        Kind::CriticAddition
+        | Kind::CriticDeletion
+        | Kind::CriticSubstitution
+        | Kind::CriticHighlight
+        | Kind::CriticComment => result.push(node),
+        _ => {
+            for child in &node.children {
+                collect(child, result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::{parse, Options};
+
+    const SOURCE: &str = "a {++b++}{--c--} {~~d~>e~~}\n{==f==}{>>g<<} h";
+
+    fn doc() -> Node {
+        let options = Options {
+            critic_markup: true,
+            ..Options::default()
+        };
+        parse(SOURCE, &options)
+    }
+
+    #[test]
+    fn test_all() {
+        assert_eq!(accept_all(SOURCE, &doc()), "a b e\nf h");
+        assert_eq!(reject_all(SOURCE, &doc()), "a c d\nf h");
+    }
+
+    #[test]
+    fn test_one() {
+        assert_eq!(
+            accept(SOURCE, &doc(), (9, 16)),
+            "a {++b++} {~~d~>e~~}\n{==f==}{>>g<<} h"
+        );
+        assert_eq!(
+            reject(SOURCE, &doc(), (17, 27)),
+            "a {++b++}{--c--} d\n{==f==}{>>g<<} h"
+        );
+        assert_eq!(accept(SOURCE, &doc(), (0, 1)), SOURCE);
+    }
+}
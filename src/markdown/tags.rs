@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::markdown::{Kind, Node};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Occurrence {
+    pub name: String,
+    pub span: (usize, usize),
+}
+
+/// Every `#hashtag` in the document, without the leading hash.
+pub fn collect_tags(source: &str, node: &Node) -> Vec<Occurrence> {
+    let mut result = vec![];
+    collect(source, node, &Kind::Hashtag, &mut result);
+    result
+}
+
+/// Every `@mention` in the document, without the leading at sign.
+pub fn collect_mentions(source: &str, node: &Node) -> Vec<Occurrence> {
+    let mut result = vec![];
+    collect(source, node, &Kind::Mention, &mut result);
+    result
+}
+
+fn collect(source: &str, node: &Node, kind: &Kind, result: &mut Vec<Occurrence>) {
+    if &node.kind == kind {
+        let (start, end) = node.span;
+        result.push(Occurrence {
+            name: source[start + 1..end].into(),
+            span: node.span,
+        });
+    }
+    for child in &node.children {
+        collect(source, child, kind, result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markdown::{parse, Options};
+
+    #[test]
+    fn test_collect() {
+        let source = "# #a\n\n* @b and #c/d\n* ~~@e~~";
+        let options = Options {
+            mentions: true,
+            hashtags: true,
+            ..Options::default()
+        };
+        let doc = parse(source, &options);
+
+        assert_eq!(
+            collect_tags(source, &doc),
+            vec![
+                Occurrence {
+                    name: "a".into(),
+                    span: (2, 4),
+                },
+                Occurrence {
+                    name: "c/d".into(),
+                    span: (15, 19),
+                },
+            ]
+        );
+        assert_eq!(
+            collect_mentions(source, &doc),
+            vec![
+                Occurrence {
+                    name: "b".into(),
+                    span: (8, 10),
+                },
+                Occurrence {
+                    name: "e".into(),
+                    span: (24, 26),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Compares building the tree as JS objects or decoding it with
+//! js/decode.js with serializing it to JSON and parsing it again, and edits
+//! with compiling from scratch. Run with `wasm-pack test --node --release`.
+#![cfg(target_arch = "wasm32")]
+
+use js_sys::{Date, JSON};
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_test::*;
+use web_sys::console;
+use writehub_webworker::Compiler;
+
+/// About a megabyte of notes, the document the library benchmarks parse.
+fn large() -> String {
+    let sample = include_str!("notes.md");
+    sample.repeat(1_000_000 / sample.len())
+}
+
+#[wasm_bindgen(module = "/js/decode.js")]
+extern "C" {
+    #[wasm_bindgen(catch)]
+    fn decode(bytes: &[u8]) -> Result<JsValue, JsValue>;
+}
+
+/// Parses the JSON of a JS value, so objects compare without key order.
+fn value(js: &JsValue) -> Value {
+    serde_json::from_str(&String::from(JSON::stringify(js).unwrap())).unwrap()
+}
+
+fn time<F: FnMut()>(name: &str, mut f: F) {
+    let runs = 5;
+    let start = Date::now();
+    for _ in 0..runs {
+        f();
+    }
+    let message = format!("{}: {:.1} ms", name, (Date::now() - start) / runs as f64);
+    console::log_1(&message.into());
+}
+
+#[wasm_bindgen_test]
+fn compile_value_matches_json() {
+    let mut compiler = Compiler::new();
+    compiler.set_math(true);
+    let source = large();
+    let json = JSON::parse(&compiler.compile_with(&source, "json").unwrap()).unwrap();
+    let value = compiler.compile_value(&source);
+    assert_eq!(
+        JSON::stringify(&value).unwrap(),
+        JSON::stringify(&json).unwrap()
+    );
+}
+
+#[wasm_bindgen_test]
+fn decode_matches_json() {
+    let mut compiler = Compiler::new();
+    compiler.set_math(true);
+    compiler.set_mentions(true);
+    compiler.set_hashtags(true);
+    compiler.set_highlight(true);
+    compiler.set_critic_markup(true);
+    compiler.set_smart_punctuation(true);
+    // Every kind of node and optional field, large() would be slow to
+    // compare as JSON values
+    let source = "# Intro {#top .a k=v}\n\n\
+                  Some ~~struck~~ ==marked== $x^2$, @me, #tag, :tada: \"quoted\" -- \
+                  <b>bold</b> <!-- c -->\n\
+                  [[Home]] [[Missing#Part|label]] ![[cat.png]] \
+                  {++add++}{~~a~>b~~}{>>note<<}\n\n\
+                  > * nested\n>   1. deep\n\n* loose\n\n* list\n";
+    let json: Value =
+        serde_json::from_str(&compiler.compile_with(source, "json").unwrap()).unwrap();
+    let bytes = compiler.compile_binary(source);
+    assert_eq!(value(&decode(&bytes).unwrap()), json);
+    // Malformed buffers are rejected instead of decoding to undefined
+    assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    assert!(decode(&[bytes.as_slice(), &[0]].concat()).is_err());
+    assert!(decode(&[bytes[0], 0, 99, 0, 0, 0]).is_err());
+    let leaf = [bytes[0], 0, 15, 1, 0, 0];
+    assert!(decode(&[&leaf[..], &[0; 16], &[5]].concat()).is_err());
+}
+
+#[wasm_bindgen_test]
+fn bench_large_document() {
+    let mut compiler = Compiler::new();
+    compiler.set_math(true);
+    let source = large();
+    time("compile_with + JSON.parse", || {
+        JSON::parse(&compiler.compile_with(&source, "json").unwrap()).unwrap();
+    });
+    time("compile_value", || {
+        compiler.compile_value(&source);
+    });
+}
+
+#[wasm_bindgen_test]
+fn edit_matches_compile() {
+    let compiler = Compiler::new();
+    let source = large();
+    compiler.compile(&source);
+    let at = source.len() / 2;
+    compiler.edit(at, at + 4, "new\n\n* item").unwrap();
+    let edited = [&source[..at], "new\n\n* item", &source[at + 4..]].concat();
+    // The tree after the edit is the one compiling the result gives
+    assert_eq!(compiler.compile(&edited), "[]");
+}
+
+#[wasm_bindgen_test]
+fn bench_edit() {
+    let compiler = Compiler::new();
+    let source = large();
+    compiler.compile(&source);
+    let at = source.len() / 2;
+    time("edit", || {
+        compiler.edit(at, at, "x").unwrap();
+    });
+    time("compile", || {
+        compiler.compile(&source);
+    });
+}